@@ -12,6 +12,11 @@ pub struct BkArgs {
 pub enum BkCommand {
     Show(ShowCommand),
     Run(RunCommand),
+    Restore(RestoreCommand),
+    Snapshots(SnapshotsCommand),
+    Init(InitCommand),
+    Cleanup(CleanupCommand),
+    CheckConfig(CheckConfigCommand),
     ConfigSchema(ConfigSchema),
 }
 
@@ -49,3 +54,100 @@ pub struct RunCommand {
     /// enable a mode of operation (module)
     pub mode: Vec<String>,
 }
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Restore a snapshot from a restic target
+#[argh(subcommand, name = "restore")]
+pub struct RestoreCommand {
+    #[argh(positional)]
+    /// config file
+    pub config: String,
+
+    #[argh(positional)]
+    /// restic target to restore from
+    pub target: String,
+
+    #[argh(positional, default = "String::from(\"latest\")")]
+    /// snapshot id (default: latest)
+    pub snapshot: String,
+
+    #[argh(option, short = 't')]
+    /// destination directory
+    pub dest: Option<String>,
+
+    #[argh(option, short = 'p')]
+    /// restore a path input to its original location
+    pub path: Option<String>,
+
+    #[argh(option, short = 'i')]
+    /// only restore files matching pattern
+    pub include: Vec<String>,
+
+    #[argh(option, short = 'e')]
+    /// exclude files matching pattern
+    pub exclude: Vec<String>,
+
+    #[argh(switch, short = 'n')]
+    /// dry run
+    pub dry_run: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List snapshots of all restic targets
+#[argh(subcommand, name = "snapshots")]
+pub struct SnapshotsCommand {
+    #[argh(positional)]
+    /// config file
+    pub config: String,
+
+    #[argh(option, short = 't')]
+    /// only list snapshots of these restic targets
+    pub target: Vec<String>,
+
+    #[argh(option)]
+    /// only list snapshots of this host
+    pub host: Vec<String>,
+
+    #[argh(option)]
+    /// only list snapshots with this tag
+    pub tag: Vec<String>,
+
+    #[argh(option, short = 'p')]
+    /// only list snapshots containing this path input
+    pub path: Vec<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Initialize a restic target
+#[argh(subcommand, name = "init")]
+pub struct InitCommand {
+    #[argh(positional)]
+    /// config file
+    pub config: String,
+
+    #[argh(positional)]
+    /// restic target to initialize
+    pub target: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Remove snapshots and mounts left behind by previous runs
+#[argh(subcommand, name = "cleanup")]
+pub struct CleanupCommand {
+    #[argh(positional)]
+    /// config file
+    pub config: String,
+
+    #[argh(switch, short = 'n')]
+    /// only list leftovers
+    pub dry_run: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Validate a config and check referenced files and binaries
+#[argh(subcommand, name = "check-config")]
+pub struct CheckConfigCommand {
+    #[argh(positional)]
+    /// config file
+    pub config: String,
+}
@@ -2,8 +2,13 @@ use yansi::{Color, Paint};
 
 use crate::{
     args::RunCommand,
-    config::{Config, RsyncConfig},
-    restic, run_command,
+    cleanup,
+    config::{Config, LockMode, RsyncConfig},
+    lock::{self, DEFAULT_LOCK_PATH, LockResult, RunLock},
+    metrics,
+    notify::Notification,
+    report::{JobKind, JobReport, RunReport},
+    restic, run_command, signal,
 };
 
 pub fn ensure_exists(dir: &str) {
@@ -27,6 +32,7 @@ pub struct ModeSelection {
     rsync: bool,
     restic: bool,
     restic_forget: bool,
+    restic_check: bool,
 }
 
 impl ModeSelection {
@@ -36,6 +42,7 @@ impl ModeSelection {
                 rsync: true,
                 restic: true,
                 restic_forget: true,
+                restic_check: true,
             };
         }
 
@@ -48,6 +55,7 @@ impl ModeSelection {
                 "rsync" => s.rsync = true,
                 "restic" => s.restic = true,
                 "restic_forget" => s.restic_forget = true,
+                "restic_check" => s.restic_check = true,
                 _ => {
                     eprintln!("Unknown mode {e}");
                     std::process::exit(1);
@@ -59,7 +67,7 @@ impl ModeSelection {
     }
 }
 
-pub fn run_backup_rsync(conf: &RsyncConfig, dry: bool) {
+pub fn run_backup_rsync(conf: &RsyncConfig, dry: bool) -> i32 {
     println!(
         "--> Running backup for {} -> {}",
         conf.src.paint(Color::Yellow),
@@ -86,22 +94,40 @@ pub fn run_backup_rsync(conf: &RsyncConfig, dry: bool) {
         }
     }
 
-    if conf.cephfs_snap.unwrap_or_default() {
-        let (snap_dir, snap_name) = cephfs_snap_create(&conf.src);
+    if let Some(snapshot) = conf.snapshot_config() {
+        let mut provider = snapshot.provider(&conf.src);
+        let snap_dir = match provider.create() {
+            Ok(snap_dir) => snap_dir,
+            Err(e) => {
+                log::error!(
+                    "{} Could not create snapshot: {e}",
+                    "Error:".paint(Color::Red)
+                );
+                return 1;
+            }
+        };
         cmd.push(&snap_dir);
         cmd.push(&conf.dest);
-        run_command(&cmd, None);
-        cephfs_snap_remove(&conf.src, &snap_name);
+        let res = run_command(&cmd, None);
+        provider.remove();
+        res.2
     } else {
         cmd.push(&conf.src);
         cmd.push(&conf.dest);
-        run_command(&cmd, None);
+        run_command(&cmd, None).2
     }
 }
 
 pub fn run_backup(args: RunCommand) -> i32 {
-    let conf = Config::from_path(&args.config);
+    let conf = match Config::from_path(&args.config) {
+        Ok(conf) => conf,
+        Err(e) => {
+            log::error!("{e}");
+            return 1;
+        }
+    };
     let mut state = 0;
+    let mut report = RunReport::new(&args.config, args.dry_run);
 
     if args.dry_run {
         log::warn!("Running in dry run mode. No backup jobs will happen.");
@@ -110,7 +136,27 @@ pub fn run_backup(args: RunCommand) -> i32 {
     if let Some(delay) = conf.delay {
         let wait = rand::random_range(0..delay);
         log::info!("Delaying backup for {wait} seconds...");
-        std::thread::sleep(std::time::Duration::from_secs(wait));
+
+        for _ in 0..wait {
+            if signal::interrupted() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    if let Some(code) = signal::exit_code() {
+        log::warn!("Interrupted before starting backup");
+        return code;
+    }
+
+    let _lock = match take_lock(&conf) {
+        Ok(lock) => lock,
+        Err(code) => return code,
+    };
+
+    if conf.cleanup.unwrap_or(true) {
+        cleanup::sweep(&conf, args.dry_run);
     }
 
     if let Some(script) = &conf.start_script {
@@ -122,13 +168,34 @@ pub fn run_backup(args: RunCommand) -> i32 {
 
     if modes.rsync {
         for rsync in &conf.rsync.unwrap_or_default() {
-            run_backup_rsync(rsync, args.dry_run);
+            if signal::interrupted() {
+                break;
+            }
+
+            let start = chrono::Utc::now();
+            let code = run_backup_rsync(rsync, args.dry_run);
+
+            if code != 0 {
+                log::error!("Rsync to {} failed with exit code {code}", rsync.dest);
+            }
+
+            report.jobs.push(JobReport::from_exit_code(
+                JobKind::Rsync,
+                &rsync.dest,
+                std::slice::from_ref(&rsync.src),
+                start,
+                code,
+            ));
         }
     }
 
     // Restic backups
     if modes.restic {
         for restic in &conf.restic.unwrap_or_default() {
+            if signal::interrupted() {
+                break;
+            }
+
             if args.exclude.iter().any(|x| restic.src.contains(x)) {
                 log::info!(
                     "Skipping restic operation due to exclude filter: exclude {:?}, got {:?}",
@@ -141,36 +208,53 @@ pub fn run_backup(args: RunCommand) -> i32 {
             let res = restic::create_archive(
                 restic,
                 conf.path.clone().unwrap_or_default(),
+                conf.dump.clone().unwrap_or_default(),
+                conf.command.clone().unwrap_or_default(),
                 conf.restic_target.clone().unwrap_or_default(),
+                conf.max_parallel,
                 args.dry_run,
             );
 
             for (target, res) in res {
                 let notify_provider = conf.ntfy.clone().unwrap_or_default();
 
-                if let Err(e) = res {
+                let job = JobReport::from_restic(JobKind::Restic, &target, &restic.src, &res);
+
+                if let Err(e) = res.result {
                     log::error!("Backup to target {target} failed: {e}");
                     state = 1;
 
                     for ntfy_key in restic.ntfy.clone().unwrap_or_default() {
-                        let ntfy_opt = notify_provider.get(&ntfy_key).unwrap();
-                        ntfy_opt.send_notification(&format!(
+                        let ntfy_opt = notify_provider
+                            .get(&ntfy_key)
+                            .expect("notifier checked by config validation");
+                        ntfy_opt.send_notification(&job.notification(format!(
                             "🚨 Backup failed for {} to {}: {e}",
                             restic.src.join(", "),
                             target
-                        ));
+                        )));
                     }
                 } else {
                     log::info!("Backup successfull for {target}");
 
+                    let summary = res
+                        .summary
+                        .as_ref()
+                        .map(|x| format!(": {x}"))
+                        .unwrap_or_default();
+
                     for ntfy_key in restic.ntfy.clone().unwrap_or_default() {
-                        let ntfy_opt = notify_provider.get(&ntfy_key).unwrap();
-                        ntfy_opt.send_notification(&format!(
-                            "✅ Backup successful for {:?} to {}",
+                        let ntfy_opt = notify_provider
+                            .get(&ntfy_key)
+                            .expect("notifier checked by config validation");
+                        ntfy_opt.send_notification(&job.notification(format!(
+                            "✅ Backup successful for {:?} to {}{summary}",
                             restic.src, target
-                        ));
+                        )));
                     }
                 }
+
+                report.jobs.push(job);
             }
         }
     }
@@ -178,6 +262,10 @@ pub fn run_backup(args: RunCommand) -> i32 {
     // Restic forget
     if modes.restic_forget {
         for restic in &conf.restic_forget.unwrap_or_default() {
+            if signal::interrupted() {
+                break;
+            }
+
             let res = restic::forget_archive(
                 restic,
                 conf.restic_target.clone().unwrap_or_default(),
@@ -187,29 +275,81 @@ pub fn run_backup(args: RunCommand) -> i32 {
             for (target, res) in res {
                 let notify_provider = conf.ntfy.clone().unwrap_or_default();
 
-                if let Err(e) = res {
+                let job = JobReport::from_restic(JobKind::ResticForget, &target, &[], &res);
+
+                if let Err(e) = res.result {
                     log::error!("Forget for target {target} failed: {e}");
                     state = 1;
 
                     for ntfy_key in restic.ntfy.clone().unwrap_or_default() {
-                        let ntfy_opt = notify_provider.get(&ntfy_key).unwrap();
-                        ntfy_opt.send_notification(&format!(
+                        let ntfy_opt = notify_provider
+                            .get(&ntfy_key)
+                            .expect("notifier checked by config validation");
+                        ntfy_opt.send_notification(&job.notification(format!(
                             "🚨 Forget failed for {} to {}: {e}",
                             restic.targets.join(", "),
                             target
-                        ));
+                        )));
                     }
                 } else {
                     log::info!("Forget successfull for {target}");
 
                     for ntfy_key in restic.ntfy.clone().unwrap_or_default() {
-                        let ntfy_opt = notify_provider.get(&ntfy_key).unwrap();
-                        ntfy_opt.send_notification(&format!(
+                        let ntfy_opt = notify_provider
+                            .get(&ntfy_key)
+                            .expect("notifier checked by config validation");
+                        ntfy_opt.send_notification(&job.notification(format!(
                             "✅ Forget successful for {:?} to {}",
                             restic.targets, target
-                        ));
+                        )));
                     }
                 }
+
+                report.jobs.push(job);
+            }
+        }
+    }
+
+    // Restic check
+    if modes.restic_check {
+        for restic in &conf.restic_check.unwrap_or_default() {
+            if signal::interrupted() {
+                break;
+            }
+
+            let res = restic::check_archive(restic, conf.restic_target.clone().unwrap_or_default());
+
+            for (target, res) in res {
+                let notify_provider = conf.ntfy.clone().unwrap_or_default();
+
+                let job = JobReport::from_restic(JobKind::ResticCheck, &target, &[], &res);
+
+                if let Err(e) = res.result {
+                    log::error!("Check for target {target} failed: {e}");
+                    state = 1;
+
+                    for ntfy_key in restic.ntfy.clone().unwrap_or_default() {
+                        let ntfy_opt = notify_provider
+                            .get(&ntfy_key)
+                            .expect("notifier checked by config validation");
+                        ntfy_opt.send_notification(
+                            &job.notification(format!("🚨 Check failed for {target}: {e}")),
+                        );
+                    }
+                } else {
+                    log::info!("Check successfull for {target}");
+
+                    for ntfy_key in restic.ntfy.clone().unwrap_or_default() {
+                        let ntfy_opt = notify_provider
+                            .get(&ntfy_key)
+                            .expect("notifier checked by config validation");
+                        ntfy_opt.send_notification(
+                            &job.notification(format!("✅ Check successful for {target}")),
+                        );
+                    }
+                }
+
+                report.jobs.push(job);
             }
         }
     }
@@ -218,45 +358,79 @@ pub fn run_backup(args: RunCommand) -> i32 {
         run_command(&["sh", script.as_str()], None);
     }
 
-    return state;
-}
+    if let Some(code) = signal::exit_code() {
+        log::warn!("Backup was interrupted. Remaining jobs were skipped.");
+        state = code;
+    }
 
-pub fn now() -> String {
-    chrono::Utc::now().format("%Y_%m_%d").to_string()
-}
+    report.finish(state);
 
-pub fn nowtime() -> String {
-    chrono::Utc::now().format("%Y_%m_%d-%H_%M").to_string()
+    if let Some(path) = &conf.report
+        && let Err(e) = report.write(path)
+    {
+        log::error!("Could not write run report to {path}: {e}");
+    }
+
+    // A dry run must not make stale backups look fresh
+    if let Some(path) = &conf.prometheus
+        && !args.dry_run
+        && let Err(e) = metrics::write_textfile(path, &report)
+    {
+        log::error!("Could not write metrics to {path}: {e}");
+    }
+
+    state
 }
 
-pub fn cephfs_snap_create(dir: &str) -> (String, String) {
-    let path = std::path::Path::new(dir);
-    let now = now();
-    let snap_name = format!("SNAP_{now}");
-    let snap_dir = path.join(".snap").join(&snap_name);
-
-    log::info!("Creating snapshot {} on {}", snap_name, dir);
-    if std::fs::create_dir(&snap_dir).is_err() {
-        if !std::fs::exists(&snap_dir).unwrap() {
-            log::error!("{} Could not create snapshot", "Error:".paint(Color::Red));
-            std::process::exit(1);
+/// Take the run lock. Returns the exit code if the run should not continue.
+fn take_lock(conf: &Config) -> Result<RunLock, i32> {
+    let lock_conf = conf.lock.clone().unwrap_or_default();
+
+    let holder = match lock::acquire(&lock_conf) {
+        LockResult::Acquired(lock) => return Ok(lock),
+        LockResult::Busy(holder) => holder,
+        LockResult::Error(e) => {
+            log::error!(
+                "Could not open run lock {}: {e}",
+                lock_conf.path.as_deref().unwrap_or(DEFAULT_LOCK_PATH)
+            );
+            return Err(1);
         }
+    };
+
+    if let Some(code) = signal::exit_code() {
+        log::warn!("Interrupted while waiting for run lock");
+        return Err(code);
     }
 
-    (format!("{}/", snap_dir.to_str().unwrap()), snap_name)
-}
+    let skip = lock_conf.mode == Some(LockMode::Skip);
 
-pub fn cephfs_snap_remove(dir: &str, snap: &str) {
-    let path = std::path::Path::new(dir);
-    let snap_dir = path.join(".snap").join(snap);
+    let message = if skip {
+        log::warn!("Skipping run: lock held by {holder}");
+        format!("⏭️ Backup skipped: lock held by {holder}")
+    } else {
+        log::error!("Run lock held by {holder}");
+        format!("🚨 Backup failed: lock held by {holder}")
+    };
+
+    let notify_provider = conf.ntfy.clone().unwrap_or_default();
+    let mut notification = Notification::new(message, skip);
+    notification.target = lock_conf
+        .path
+        .as_deref()
+        .unwrap_or(DEFAULT_LOCK_PATH)
+        .to_string();
+
+    for ntfy_key in lock_conf.ntfy.iter().flatten() {
+        let ntfy_opt = notify_provider
+            .get(ntfy_key)
+            .expect("notifier checked by config validation");
+        ntfy_opt.send_notification(&notification);
+    }
 
-    log::info!("Removing snapshot {} on {}", snap, dir);
-    std::fs::remove_dir(snap_dir).unwrap()
+    Err(if skip { 0 } else { 1 })
 }
 
-pub fn cephfs_snap_remove_dir(dir: &str) {
-    let path = std::path::Path::new(dir);
-
-    log::info!("Removing snapshot {}", path.to_str().unwrap());
-    std::fs::remove_dir(path).unwrap()
+pub fn nowtime() -> String {
+    chrono::Utc::now().format("%Y_%m_%d-%H_%M").to_string()
 }
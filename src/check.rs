@@ -0,0 +1,189 @@
+use std::os::unix::fs::PermissionsExt;
+
+use yansi::{Color, Paint};
+
+use crate::{args::CheckConfigCommand, config::Config};
+
+/// Outcome of a single check
+struct Check {
+    name: String,
+    result: Result<(), String>,
+}
+
+impl Check {
+    fn print(&self) {
+        match &self.result {
+            Ok(()) => println!("{} {}", "PASS".paint(Color::Green), self.name),
+            Err(e) => println!("{} {}: {e}", "FAIL".paint(Color::Red), self.name),
+        }
+    }
+}
+
+pub fn run_check_config(args: CheckConfigCommand) -> i32 {
+    let conf = match Config::from_path(&args.config) {
+        Ok(conf) => conf,
+        Err(e) => {
+            Check {
+                name: format!("config {}", args.config),
+                result: Err(e.to_string()),
+            }
+            .print();
+            return 1;
+        }
+    };
+
+    let mut checks = vec![Check {
+        name: format!("config {}", args.config),
+        result: Ok(()),
+    }];
+
+    for (name, file) in files(&conf) {
+        checks.push(Check {
+            result: readable(&file),
+            name: format!("{name} {file}"),
+        });
+    }
+
+    for binary in binaries(&conf) {
+        checks.push(Check {
+            result: on_path(&binary),
+            name: format!("binary {binary}"),
+        });
+    }
+
+    for check in &checks {
+        check.print();
+    }
+
+    let failed = checks.iter().filter(|x| x.result.is_err()).count();
+    println!("{} checks, {failed} failed", checks.len());
+
+    if failed == 0 { 0 } else { 1 }
+}
+
+/// Files referenced by the config which have to be readable, with a description
+fn files(conf: &Config) -> Vec<(String, String)> {
+    let mut files = Vec::new();
+
+    if let Some(script) = &conf.start_script {
+        files.push(("start_script".to_string(), script.clone()));
+    }
+
+    if let Some(script) = &conf.end_script {
+        files.push(("end_script".to_string(), script.clone()));
+    }
+
+    let mut targets: Vec<_> = conf.restic_target.iter().flatten().collect();
+    targets.sort_by_key(|(name, _)| *name);
+
+    for (name, target) in targets {
+        let s3 = target.s3.as_ref();
+
+        for (option, file) in [
+            ("passphrase_file", target.passphrase_file.as_ref()),
+            (
+                "s3.access_key_file",
+                s3.and_then(|x| x.access_key_file.as_ref()),
+            ),
+            (
+                "s3.secret_key_file",
+                s3.and_then(|x| x.secret_key_file.as_ref()),
+            ),
+            ("ssh.identity", target.ssh.as_ref().map(|x| &x.identity)),
+        ] {
+            if let Some(file) = file {
+                files.push((format!("restic_target.{name}.{option}"), file.clone()));
+            }
+        }
+    }
+
+    let mut dumps: Vec<_> = conf.dump.iter().flatten().collect();
+    dumps.sort_by_key(|(name, _)| *name);
+
+    for (name, dump) in dumps {
+        if let Some(file) = &dump.password_file {
+            files.push((format!("dump.{name}.password_file"), file.clone()));
+        }
+    }
+
+    let mut notifiers: Vec<_> = conf.ntfy.iter().flatten().collect();
+    notifiers.sort_by_key(|(name, _)| *name);
+
+    for (name, notifier) in notifiers {
+        let ntfy_pass = notifier
+            .ntfy
+            .as_ref()
+            .and_then(|x| x.auth.as_ref())
+            .and_then(|x| x.pass_file.as_ref());
+        let email_pass = notifier.email.as_ref().and_then(|x| x.pass_file.as_ref());
+
+        for (option, file) in [("ntfy", ntfy_pass), ("email", email_pass)] {
+            if let Some(file) = file {
+                files.push((format!("ntfy.{name}.{option}.pass_file"), file.clone()));
+            }
+        }
+    }
+
+    files
+}
+
+/// Binaries needed by the configured jobs and inputs
+fn binaries(conf: &Config) -> Vec<String> {
+    let mut binaries = Vec::new();
+
+    let uses_restic = conf.restic.as_ref().is_some_and(|x| !x.is_empty())
+        || conf.restic_forget.as_ref().is_some_and(|x| !x.is_empty())
+        || conf.restic_check.as_ref().is_some_and(|x| !x.is_empty());
+
+    if uses_restic {
+        binaries.push("restic".to_string());
+    }
+
+    if conf.rsync.as_ref().is_some_and(|x| !x.is_empty()) {
+        binaries.push("rsync".to_string());
+    }
+
+    for dump in conf.dump.iter().flatten().map(|(_, x)| x) {
+        binaries.push(dump.command()[0].clone());
+    }
+
+    for command in conf.command.iter().flatten().map(|(_, x)| x) {
+        if let Some(binary) = command.command.first() {
+            binaries.push(binary.clone());
+        }
+    }
+
+    binaries.sort();
+    binaries.dedup();
+    binaries
+}
+
+fn readable(file: &str) -> Result<(), String> {
+    std::fs::File::open(file)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Whether `binary` is executable, searching `PATH` if it is not a path
+fn on_path(binary: &str) -> Result<(), String> {
+    let executable = |path: &std::path::Path| {
+        path.metadata()
+            .is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+    };
+
+    if binary.contains('/') {
+        return if executable(std::path::Path::new(binary)) {
+            Ok(())
+        } else {
+            Err("not executable".to_string())
+        };
+    }
+
+    let path = std::env::var_os("PATH").unwrap_or_default();
+
+    if std::env::split_paths(&path).any(|dir| executable(&dir.join(binary))) {
+        Ok(())
+    } else {
+        Err("not found on PATH".to_string())
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, DurationRound, NaiveDateTime, TimeDelta, Utc};
+use yansi::{Color, Paint};
+
+use crate::{
+    args::CleanupCommand,
+    config::Config,
+    lock::{self, DEFAULT_LOCK_PATH, LockResult},
+    run_command, signal,
+    snapshot::{SnapshotConfig, parse_time},
+};
+
+/// Directory consistent bind mounts are created in
+pub const MOUNT_DIR: &str = "/bk/";
+
+/// Leftover of a previous (crashed) run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Leftover {
+    /// Bind mount below `/bk`
+    Mount(String),
+    /// Snapshot of `dir` as listed by its snapshot provider
+    Snapshot {
+        kind: SnapshotConfig,
+        dir: String,
+        snapshot: String,
+    },
+}
+
+impl std::fmt::Display for Leftover {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Leftover::Mount(mount) => write!(f, "mount {mount}"),
+            Leftover::Snapshot { kind, snapshot, .. } => write!(f, "{kind} snapshot {snapshot}"),
+        }
+    }
+}
+
+/// Find all bind mounts and snapshots left behind by runs of `conf` started before `started`.
+///
+/// Only call this while holding the run lock. Leftovers are recognized by the time in their
+/// name, which only has minute precision, so snapshots of the current minute are kept.
+/// Mounts come first so they are removed before the snapshots they point to.
+pub fn find_leftovers(conf: &Config, started: DateTime<Utc>) -> Vec<Leftover> {
+    let before = started
+        .duration_trunc(TimeDelta::minutes(1))
+        .unwrap_or(started)
+        .naive_utc();
+
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    let mut leftovers: Vec<_> = stale_mounts(&mountinfo, before)
+        .into_iter()
+        .map(Leftover::Mount)
+        .collect();
+
+    let mut inputs: Vec<(SnapshotConfig, String)> = conf
+        .path
+        .iter()
+        .flatten()
+        .filter_map(|(_, x)| Some((x.snapshot_config()?, x.path.clone())))
+        .collect();
+
+    inputs.extend(
+        conf.rsync
+            .iter()
+            .flatten()
+            .filter_map(|x| Some((x.snapshot_config()?, x.src.clone()))),
+    );
+
+    let mut seen = HashSet::new();
+
+    for (kind, dir) in inputs {
+        // Paths on the same dataset or volume share their snapshots
+        for snapshot in kind.provider(&dir).stale(before) {
+            if seen.insert(snapshot.clone()) {
+                leftovers.push(Leftover::Snapshot {
+                    kind: kind.clone(),
+                    dir: dir.clone(),
+                    snapshot,
+                });
+            }
+        }
+    }
+
+    leftovers
+}
+
+/// Remove `leftovers`. Returns the number of leftovers which could not be removed.
+///
+/// Stops early if a signal was received. The remaining leftovers count as failed.
+pub fn remove_leftovers(leftovers: &[Leftover]) -> usize {
+    let mut failed = 0;
+
+    for (i, leftover) in leftovers.iter().enumerate() {
+        if signal::interrupted() {
+            log::warn!("Interrupted. Remaining leftovers were not removed.");
+            return failed + leftovers.len() - i;
+        }
+
+        log::info!("Removing leftover {leftover}");
+
+        let ok = match leftover {
+            Leftover::Mount(mount) => run_command(&["umount", mount], None).2 == 0,
+            Leftover::Snapshot {
+                kind,
+                dir,
+                snapshot,
+            } => kind.provider(dir).remove_stale(snapshot),
+        };
+
+        if !ok {
+            failed += 1;
+        }
+    }
+
+    failed
+}
+
+/// Clean up leftovers before a run. Only call this while holding the run lock.
+pub fn sweep(conf: &Config, dry: bool) {
+    let leftovers = find_leftovers(conf, Utc::now());
+
+    if leftovers.is_empty() {
+        return;
+    }
+
+    log::warn!(
+        "Found {} leftovers of previous runs: {}",
+        leftovers.len(),
+        leftovers
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    if !dry {
+        remove_leftovers(&leftovers);
+    }
+}
+
+pub fn run_cleanup(args: CleanupCommand) -> i32 {
+    let conf = match Config::from_path(&args.config) {
+        Ok(conf) => conf,
+        Err(e) => {
+            log::error!("{e}");
+            return 1;
+        }
+    };
+
+    // Leftovers of a running backup are still in use
+    let lock_conf = conf.lock.clone().unwrap_or_default();
+    let _lock = match lock::acquire(&lock_conf) {
+        LockResult::Acquired(lock) => lock,
+        LockResult::Busy(holder) => {
+            log::error!("Run lock held by {holder}");
+            return signal::exit_code().unwrap_or(1);
+        }
+        LockResult::Error(e) => {
+            log::error!(
+                "Could not open run lock {}: {e}",
+                lock_conf.path.as_deref().unwrap_or(DEFAULT_LOCK_PATH)
+            );
+            return 1;
+        }
+    };
+
+    let leftovers = find_leftovers(&conf, Utc::now());
+
+    if leftovers.is_empty() {
+        log::info!("Nothing to clean up");
+        return 0;
+    }
+
+    for leftover in &leftovers {
+        println!("{} {leftover}", "-->".paint(Color::Yellow));
+    }
+
+    if args.dry_run {
+        return 0;
+    }
+
+    let failed = remove_leftovers(&leftovers);
+
+    if let Some(code) = signal::exit_code() {
+        code
+    } else if failed == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+/// Mount points below `/bk` of snapshots taken before `before`, deepest first.
+///
+/// Mounts without a recognizable snapshot time are kept.
+fn stale_mounts(mountinfo: &str, before: NaiveDateTime) -> Vec<String> {
+    let mut stale = Vec::new();
+
+    for (mount, time) in bk_mounts(mountinfo) {
+        match time {
+            Some(time) if time < before => stale.push(mount),
+            Some(_) => {}
+            None => log::warn!("Not removing mount {mount}: unknown snapshot time"),
+        }
+    }
+
+    stale
+}
+
+/// Mount points below `/bk` in the content of `/proc/self/mountinfo` with the time of the
+/// mounted snapshot, deepest first
+fn bk_mounts(mountinfo: &str) -> Vec<(String, Option<NaiveDateTime>)> {
+    let mut mounts: Vec<_> = mountinfo
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split(' ').collect();
+            let mount = unescape_mountinfo(fields.get(4)?);
+
+            if !mount.starts_with(MOUNT_DIR) {
+                return None;
+            }
+
+            // The root of bind mounts and the source of LVM and ZFS mounts name the snapshot
+            let root = unescape_mountinfo(fields.get(3)?);
+            let source = fields
+                .iter()
+                .position(|x| *x == "-")
+                .and_then(|x| fields.get(x + 2))
+                .map(|x| unescape_mountinfo(x))
+                .unwrap_or_default();
+
+            Some((mount, mount_time(&root).or_else(|| mount_time(&source))))
+        })
+        .collect();
+
+    mounts.sort_by(|a, b| b.0.cmp(&a.0));
+    mounts.dedup();
+    mounts
+}
+
+/// Time of the bk snapshot named in a mount root or source, e.g. `/data/.snap/SNAP_<time>`,
+/// `tank/data@bk-<time>` or `/dev/mapper/vg-lv_bk_<time>`
+fn mount_time(name: &str) -> Option<NaiveDateTime> {
+    let (start, len) = ["SNAP_", "@bk-", "_bk_"]
+        .iter()
+        .filter_map(|marker| Some((name.rfind(marker)?, marker.len())))
+        .max()?;
+
+    let time = name[start + len..].split('/').next()?;
+
+    // Device mapper names escape `-` as `--`
+    parse_time(&time.replace("--", "-"))
+}
+
+/// Decode the octal escapes (`\040` for space) used in mountinfo
+fn unescape_mountinfo(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 3 < bytes.len()
+            && let Ok(c) = u8::from_str_radix(&field[i + 1..i + 4], 8)
+        {
+            out.push(c);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&out).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = r"22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+1001 22 0:55 /data/.snap/SNAP_2026_10_17-10_05 /bk/_data rw,relatime shared:500 - ceph 10.0.0.1:6789:/ rw,name=admin
+1002 22 0:60 / /bk/_tank_data ro,relatime shared:501 - zfs tank/data@bk-2026_10_17-10_06 ro
+1003 22 253:3 / /bk/lvm_vg_data ro,relatime shared:502 - xfs /dev/mapper/vg-data_bk_2026_10_17--10_07 ro,nouuid
+1004 22 0:55 /my\040data/.snap/SNAP_2026_10_17-10_08 /bk/_my\040data rw,relatime shared:503 - ceph 10.0.0.1:6789:/ rw
+1005 22 8:1 /home /bk/_home rw,relatime shared:504 - ext4 /dev/sda1 rw
+1006 22 0:55 /old/.snap/SNAP_2026_10_16 /bk/_old rw,relatime shared:505 - ceph 10.0.0.1:6789:/ rw
+1007 22 0:61 / /mnt/other ro - zfs tank/other@bk-2026_10_17-10_05 ro";
+
+    fn time(s: &str) -> Option<NaiveDateTime> {
+        parse_time(s)
+    }
+
+    #[test]
+    fn mounts_with_snapshot_time() {
+        assert_eq!(
+            bk_mounts(MOUNTINFO),
+            [
+                ("/bk/lvm_vg_data".to_string(), time("2026_10_17-10_07")),
+                ("/bk/_tank_data".to_string(), time("2026_10_17-10_06")),
+                ("/bk/_old".to_string(), time("2026_10_16")),
+                ("/bk/_my data".to_string(), time("2026_10_17-10_08")),
+                ("/bk/_home".to_string(), None),
+                ("/bk/_data".to_string(), time("2026_10_17-10_05")),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_older_mounts_are_stale() {
+        let before = time("2026_10_17-10_07").unwrap();
+
+        // The mount without a time and the ones of the current minute or later are kept
+        assert_eq!(
+            stale_mounts(MOUNTINFO, before),
+            ["/bk/_tank_data", "/bk/_old", "/bk/_data"]
+        );
+    }
+
+    #[test]
+    fn snapshot_time_in_names() {
+        assert_eq!(
+            mount_time("/data/.snap/SNAP_2026_10_17-10_05"),
+            time("2026_10_17-10_05")
+        );
+        assert_eq!(
+            mount_time("/data/.bk_snap/SNAP_2026_10_17-10_05/sub"),
+            time("2026_10_17-10_05")
+        );
+        assert_eq!(
+            mount_time("/dev/mapper/my--vg-my--lv_bk_2026_10_17--10_05"),
+            time("2026_10_17-10_05")
+        );
+        assert_eq!(mount_time("/data/.snap/SNAP_manual"), None);
+        assert_eq!(mount_time("/dev/sda1"), None);
+    }
+
+    #[test]
+    fn unescape() {
+        assert_eq!(unescape_mountinfo(r"/a\040b\011c\134d"), "/a b\tc\\d");
+        assert_eq!(unescape_mountinfo(r"/trailing\04"), r"/trailing\04");
+    }
+}
@@ -1,17 +1,23 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, error::Error};
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use toml::Spanned;
 
 use crate::{
-    backup::{cephfs_snap_create, cephfs_snap_remove, ensure_exists},
-    notify::ntfy,
+    backup::ensure_exists,
+    cleanup::MOUNT_DIR,
+    notify::{Notification, Notifier, email, ntfy, webhook},
     restic::{bind_mount, find_password, umount},
+    snapshot::{SnapshotConfig, SnapshotProvider},
 };
 
 /// Configuration structure for the backup system.
-#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct Config {
+    /// Additional config files to merge into this one. Only the file name may contain `*` and `?`.
+    pub include: Option<Vec<String>>,
+
     /// Optional script to run before starting the backup process.
     pub start_script: Option<String>,
 
@@ -21,10 +27,31 @@ pub struct Config {
     /// Optional Max Jitter Delay in seconds. Randomized wait time to evenly distribute backups if started via exact cron for example
     pub delay: Option<u64>,
 
+    /// Remove snapshots and mounts left behind by previous runs before starting (default true)
+    pub cleanup: Option<bool>,
+
+    /// Optional path to write a JSON report to after every run
+    pub report: Option<String>,
+
+    /// Optional path to write Prometheus metrics to after every run (node_exporter textfile collector)
+    pub prometheus: Option<String>,
+
+    /// Maximum number of targets backed up concurrently by jobs with `parallel_targets`
+    pub max_parallel: Option<usize>,
+
+    /// Lock preventing overlapping runs. Always taken, this only changes its path and mode.
+    pub lock: Option<LockConfig>,
+
     // CDRs
     /// Local path inputs
     pub path: Option<HashMap<String, LocalPath>>,
 
+    /// Database dump inputs
+    pub dump: Option<HashMap<String, DumpInput>>,
+
+    /// Command output inputs
+    pub command: Option<HashMap<String, CommandInput>>,
+
     /// Configuration for rsync jobs.
     pub rsync: Option<Vec<RsyncConfig>>,
 
@@ -37,16 +64,630 @@ pub struct Config {
     /// Configuration for restic forget jobs
     pub restic_forget: Option<Vec<ResticForget>>,
 
+    /// Configuration for restic check jobs
+    pub restic_check: Option<Vec<ResticCheck>>,
+
     /// Ntfy targets
     pub ntfy: Option<HashMap<String, NtfyTarget>>,
 }
 
 impl Config {
-    pub fn from_path(path: &str) -> Self {
-        toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    /// Read, parse and validate the config file at `path` including all files it includes
+    pub fn from_path(path: &str) -> Result<Self, ConfigError> {
+        let main = ConfigFile::load(path)?;
+        let mut files = Vec::new();
+
+        for pattern in main.conf.include.iter().flatten() {
+            for file in expand_include(path, pattern)? {
+                let file = ConfigFile::load(&file)?;
+
+                if file.conf.include.is_some() {
+                    return Err(ConfigError::NestedInclude { path: file.path });
+                }
+
+                files.push(file);
+            }
+        }
+
+        files.insert(0, main);
+
+        let mut conf = Config::default();
+        let mut defined = HashMap::new();
+
+        for file in &files {
+            conf.merge(file.conf.clone(), &file.path, &mut defined)?;
+        }
+
+        let mut errors = Vec::new();
+
+        for file in &files {
+            errors.extend(conf.validate(file));
+        }
+
+        if !errors.is_empty() {
+            return Err(ConfigError::Invalid {
+                path: path.to_string(),
+                errors,
+            });
+        }
+
+        Ok(conf)
+    }
+
+    /// Copy of the config with inline secrets replaced, e.g. for printing it
+    pub fn redacted(&self) -> Self {
+        let mut conf = self.clone();
+        let redact = |x: &mut Option<String>| {
+            if x.is_some() {
+                *x = Some(REDACTED.to_string());
+            }
+        };
+
+        for target in conf.restic_target.iter_mut().flat_map(|x| x.values_mut()) {
+            redact(&mut target.passphrase);
+
+            if let Some(s3) = &mut target.s3 {
+                redact(&mut s3.access_key);
+                redact(&mut s3.secret_key);
+            }
+        }
+
+        for dump in conf.dump.iter_mut().flat_map(|x| x.values_mut()) {
+            redact(&mut dump.password);
+        }
+
+        for target in conf.ntfy.iter_mut().flat_map(|x| x.values_mut()) {
+            if let Some(auth) = target.ntfy.as_mut().and_then(|x| x.auth.as_mut()) {
+                redact(&mut auth.pass);
+            }
+
+            if let Some(email) = &mut target.email {
+                redact(&mut email.pass);
+            }
+
+            // URLs and headers usually carry tokens
+            if let Some(webhook) = &mut target.webhook {
+                webhook.url = REDACTED.to_string();
+
+                for header in webhook.headers.iter_mut().flat_map(|x| x.values_mut()) {
+                    *header = REDACTED.to_string();
+                }
+            }
+        }
+
+        conf
+    }
+
+    /// Merge the config `other` read from `file` into this one.
+    ///
+    /// Maps are merged and lists concatenated. `defined` tracks which file defined which key.
+    fn merge(
+        &mut self,
+        other: Config,
+        file: &str,
+        defined: &mut HashMap<String, String>,
+    ) -> Result<(), ConfigError> {
+        let mut define = |key: String| match defined.get(&key) {
+            Some(first) => Err(ConfigError::Duplicate {
+                key,
+                first: first.clone(),
+                second: file.to_string(),
+            }),
+            None => {
+                defined.insert(key, file.to_string());
+                Ok(())
+            }
+        };
+
+        merge_value(&mut self.include, other.include, "include", &mut define)?;
+        merge_value(
+            &mut self.start_script,
+            other.start_script,
+            "start_script",
+            &mut define,
+        )?;
+        merge_value(
+            &mut self.end_script,
+            other.end_script,
+            "end_script",
+            &mut define,
+        )?;
+        merge_value(&mut self.delay, other.delay, "delay", &mut define)?;
+        merge_value(&mut self.cleanup, other.cleanup, "cleanup", &mut define)?;
+        merge_value(&mut self.report, other.report, "report", &mut define)?;
+        merge_value(
+            &mut self.prometheus,
+            other.prometheus,
+            "prometheus",
+            &mut define,
+        )?;
+        merge_value(
+            &mut self.max_parallel,
+            other.max_parallel,
+            "max_parallel",
+            &mut define,
+        )?;
+        merge_value(&mut self.lock, other.lock, "lock", &mut define)?;
+
+        merge_map(&mut self.path, other.path, "path", &mut define)?;
+        merge_map(&mut self.dump, other.dump, "dump", &mut define)?;
+        merge_map(&mut self.command, other.command, "command", &mut define)?;
+        merge_map(
+            &mut self.restic_target,
+            other.restic_target,
+            "restic_target",
+            &mut define,
+        )?;
+        merge_map(&mut self.ntfy, other.ntfy, "ntfy", &mut define)?;
+
+        merge_list(&mut self.rsync, other.rsync);
+        merge_list(&mut self.restic, other.restic);
+        merge_list(&mut self.restic_forget, other.restic_forget);
+        merge_list(&mut self.restic_check, other.restic_check);
+
+        Ok(())
+    }
+
+    /// Check that all references in `file` to other sections resolve
+    fn validate(&self, file: &ConfigFile) -> Vec<ValidationError> {
+        let is_source = |name: &str| {
+            has_key(&self.path, name) || has_key(&self.dump, name) || has_key(&self.command, name)
+        };
+        let is_target = |name: &str| has_key(&self.restic_target, name);
+        let is_ntfy = |name: &str| has_key(&self.ntfy, name);
+
+        let refs = &file.refs;
+        let jobs = refs
+            .restic
+            .iter()
+            .chain(&refs.restic_forget)
+            .chain(&refs.restic_check)
+            .flatten()
+            .chain(&refs.lock);
+
+        let mut errors = Vec::new();
+
+        // `src` could not tell which input is meant
+        let inputs = refs
+            .path
+            .iter()
+            .chain(&refs.dump)
+            .chain(&refs.command)
+            .flat_map(|x| x.keys());
+
+        for name in inputs {
+            let sections: Vec<_> = [
+                ("path", has_key(&self.path, name.get_ref())),
+                ("dump", has_key(&self.dump, name.get_ref())),
+                ("command", has_key(&self.command, name.get_ref())),
+            ]
+            .into_iter()
+            .filter_map(|(section, defined)| defined.then_some(section))
+            .collect();
+
+            if sections.len() > 1 {
+                errors.push(ValidationError::new(
+                    file,
+                    name.span().start,
+                    format!(
+                        "input `{}` is defined as {}",
+                        name.get_ref(),
+                        sections.join(" and ")
+                    ),
+                ));
+            }
+        }
+
+        for job in jobs {
+            let mut check =
+                |names: &[Spanned<String>], kind: &str, exists: &dyn Fn(&str) -> bool| {
+                    for name in names.iter().filter(|x| !exists(x.get_ref())) {
+                        errors.push(ValidationError::new(
+                            file,
+                            name.span().start,
+                            format!("unknown {kind} `{}`", name.get_ref()),
+                        ));
+                    }
+                };
+
+            check(&job.src, "input", &is_source);
+            check(&job.targets, "restic target", &is_target);
+            check(&job.ntfy, "notifier", &is_ntfy);
+        }
+
+        errors.sort_by_key(|x| (x.line, x.column));
+        errors
     }
 }
 
+fn has_key<T>(map: &Option<HashMap<String, T>>, name: &str) -> bool {
+    map.as_ref().is_some_and(|x| x.contains_key(name))
+}
+
+fn merge_value<T>(
+    into: &mut Option<T>,
+    from: Option<T>,
+    key: &str,
+    define: &mut impl FnMut(String) -> Result<(), ConfigError>,
+) -> Result<(), ConfigError> {
+    if from.is_some() {
+        define(key.to_string())?;
+        *into = from;
+    }
+    Ok(())
+}
+
+fn merge_map<T>(
+    into: &mut Option<HashMap<String, T>>,
+    from: Option<HashMap<String, T>>,
+    section: &str,
+    define: &mut impl FnMut(String) -> Result<(), ConfigError>,
+) -> Result<(), ConfigError> {
+    for (name, value) in from.into_iter().flatten() {
+        define(format!("{section}.{name}"))?;
+        into.get_or_insert_default().insert(name, value);
+    }
+    Ok(())
+}
+
+fn merge_list<T>(into: &mut Option<Vec<T>>, from: Option<Vec<T>>) {
+    if let Some(from) = from {
+        into.get_or_insert_default().extend(from);
+    }
+}
+
+/// Files matching the include `pattern`, sorted.
+///
+/// Relative patterns are resolved against the directory of `config`. Only the file name may contain `*` and `?`.
+fn expand_include(config: &str, pattern: &str) -> Result<Vec<String>, ConfigError> {
+    let base = std::path::Path::new(config)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    let pattern = base.join(pattern);
+
+    let name = pattern
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or_default();
+
+    if !name.contains(['*', '?']) {
+        return Ok(vec![pattern.to_string_lossy().to_string()]);
+    }
+
+    let dir = pattern.parent().unwrap_or(std::path::Path::new(""));
+    let entries = std::fs::read_dir(dir).map_err(|error| ConfigError::Io {
+        path: dir.to_string_lossy().to_string(),
+        error,
+    })?;
+
+    let mut files: Vec<_> = entries
+        .flatten()
+        .filter(|x| x.file_type().is_ok_and(|x| !x.is_dir()))
+        .filter(|x| glob_match(name, &x.file_name().to_string_lossy()))
+        .map(|x| x.path().to_string_lossy().to_string())
+        .collect();
+
+    files.sort();
+    Ok(files)
+}
+
+/// Match `name` against a pattern with `*` (any characters) and `?` (one character)
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it matched up to
+    let mut star = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Replacement for secrets in [`Config::redacted`]
+const REDACTED: &str = "<redacted>";
+
+/// A single parsed config file
+struct ConfigFile {
+    path: String,
+    content: String,
+    conf: Config,
+    refs: ConfigRefs,
+}
+
+impl ConfigFile {
+    fn load(path: &str) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+
+        let parse_error = |error| ConfigError::Parse {
+            path: path.to_string(),
+            error,
+        };
+
+        // Check types on the raw file first to report errors with their location
+        toml::from_str::<Config>(&content).map_err(parse_error)?;
+
+        let mut table = toml::Value::Table(toml::from_str(&content).map_err(parse_error)?);
+        let mut errors = Vec::new();
+        interpolate_value(&mut table, "", &mut errors);
+
+        if !errors.is_empty() {
+            return Err(ConfigError::Interpolation {
+                path: path.to_string(),
+                errors,
+            });
+        }
+
+        // References keep their location in the raw file but are checked with resolved names
+        let mut refs: ConfigRefs = toml::from_str(&content).map_err(parse_error)?;
+        refs.interpolate();
+
+        Ok(Self {
+            path: path.to_string(),
+            conf: table.try_into().map_err(parse_error)?,
+            refs,
+            content,
+        })
+    }
+}
+
+/// Resolve `${env:VAR}` and `${file:/path}` in all strings of `value`.
+///
+/// Problems are collected in `errors` together with the key they occurred in.
+fn interpolate_value(value: &mut toml::Value, key: &str, errors: &mut Vec<String>) {
+    match value {
+        toml::Value::String(s) => match interpolate(s) {
+            Ok(resolved) => *s = resolved,
+            Err(e) => errors.push(format!("{key}: {e}")),
+        },
+        toml::Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                interpolate_value(value, &format!("{key}[{i}]"), errors);
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{key}.{name}")
+                };
+                interpolate_value(value, &key, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace `${env:VAR}` and `${file:/path}` in `value`. `$${` is a literal `${`.
+fn interpolate(value: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        out.push_str(&rest[..start]);
+
+        let Some(len) = rest[start..].find('}') else {
+            return Err("unterminated `${`".to_string());
+        };
+
+        let expr = &rest[start + 2..start + len];
+        out.push_str(&resolve(expr)?);
+        rest = &rest[start + len + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Value of a single `${kind:arg}` expression
+fn resolve(expr: &str) -> Result<String, String> {
+    match expr.split_once(':') {
+        Some(("env", var)) => {
+            std::env::var(var).map_err(|_| format!("environment variable {var} is not set"))
+        }
+        Some(("file", path)) => std::fs::read_to_string(path)
+            .map(|x| x.trim_end_matches(['\n', '\r']).to_string())
+            .map_err(|e| format!("could not read {path}: {e}")),
+        _ => Err(format!("unknown interpolation `${{{expr}}}`")),
+    }
+}
+
+/// References to other sections and input names with their location in the config file
+#[derive(Deserialize)]
+struct ConfigRefs {
+    path: Option<HashMap<Spanned<String>, serde::de::IgnoredAny>>,
+    dump: Option<HashMap<Spanned<String>, serde::de::IgnoredAny>>,
+    command: Option<HashMap<Spanned<String>, serde::de::IgnoredAny>>,
+    restic: Option<Vec<JobRefs>>,
+    restic_forget: Option<Vec<JobRefs>>,
+    restic_check: Option<Vec<JobRefs>>,
+    lock: Option<JobRefs>,
+}
+
+impl ConfigRefs {
+    /// Resolve placeholders in the referenced names. The config itself was already resolved
+    /// successfully, so this cannot fail.
+    fn interpolate(&mut self) {
+        let jobs = self
+            .restic
+            .iter_mut()
+            .chain(&mut self.restic_forget)
+            .chain(&mut self.restic_check)
+            .flatten()
+            .chain(&mut self.lock);
+
+        for job in jobs {
+            for name in job
+                .src
+                .iter_mut()
+                .chain(&mut job.targets)
+                .chain(&mut job.ntfy)
+            {
+                if let Ok(resolved) = interpolate(name.get_ref()) {
+                    *name.get_mut() = resolved;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JobRefs {
+    #[serde(default)]
+    src: Vec<Spanned<String>>,
+    #[serde(default)]
+    targets: Vec<Spanned<String>>,
+    #[serde(default)]
+    ntfy: Vec<Spanned<String>>,
+}
+
+/// Error loading the config file
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A file could not be read
+    Io { path: String, error: std::io::Error },
+    /// A file is not valid TOML or does not match the config structure
+    Parse {
+        path: String,
+        error: toml::de::Error,
+    },
+    /// `${...}` placeholders which could not be resolved
+    Interpolation { path: String, errors: Vec<String> },
+    /// An included file includes other files
+    NestedInclude { path: String },
+    /// A key is defined in more than one file
+    Duplicate {
+        key: String,
+        first: String,
+        second: String,
+    },
+    /// The config references sections which do not exist
+    Invalid {
+        path: String,
+        errors: Vec<ValidationError>,
+    },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => write!(f, "Could not read config {path}: {error}"),
+            ConfigError::Parse { path, error } => write!(f, "Invalid config {path}: {error}"),
+            ConfigError::Interpolation { path, errors } => {
+                write!(f, "Could not resolve values in config {path}:")?;
+                for e in errors {
+                    write!(f, "\n  {e}")?;
+                }
+                Ok(())
+            }
+            ConfigError::NestedInclude { path } => {
+                write!(f, "Included config {path} can not include other files")
+            }
+            ConfigError::Duplicate { key, first, second } => {
+                write!(f, "`{key}` is defined in both {first} and {second}")
+            }
+            ConfigError::Invalid { path, errors } => {
+                write!(f, "Invalid config {path}:")?;
+                for e in errors {
+                    write!(f, "\n  {e}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// A problem found while validating the config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The config file containing the problem
+    pub path: String,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column
+    pub column: usize,
+    pub message: String,
+}
+
+impl ValidationError {
+    /// Error at byte `offset` of `file`
+    fn new(file: &ConfigFile, offset: usize, message: String) -> Self {
+        let before = &file.content[..offset];
+        let line_start = before.rfind('\n').map(|x| x + 1).unwrap_or(0);
+
+        Self {
+            path: file.path.clone(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.path, self.line, self.column, self.message
+        )
+    }
+}
+
+/// Run lock configuration
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct LockConfig {
+    /// Path of the lock file (default `/run/bk.lock`)
+    pub path: Option<String>,
+
+    /// What to do if another run holds the lock (default `wait`)
+    pub mode: Option<LockMode>,
+
+    /// Notifications if a run is skipped or fails because of the lock
+    pub ntfy: Option<Vec<String>>,
+}
+
+/// Behaviour if another run holds the lock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LockMode {
+    /// Wait until the other run finished
+    #[default]
+    Wait,
+    /// Exit successfully without running
+    Skip,
+    /// Exit with an error without running
+    Fail,
+}
+
 /// Configuration for an individual rsync job.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct RsyncConfig {
@@ -65,8 +706,24 @@ pub struct RsyncConfig {
     /// Ensure a specific directory exists before running the rsync job.
     pub ensure_exists: Option<String>,
 
-    /// Create CephFS snapshot before the rsync job.
+    /// Create CephFS snapshot before the rsync job. Same as `snapshot = { kind = "cephfs" }`
     pub cephfs_snap: Option<bool>,
+
+    /// Snapshot to take before the rsync job
+    pub snapshot: Option<SnapshotConfig>,
+}
+
+impl RsyncConfig {
+    /// Snapshot taken before the job, including the legacy `cephfs_snap` option
+    pub fn snapshot_config(&self) -> Option<SnapshotConfig> {
+        if self.snapshot.is_some() {
+            self.snapshot.clone()
+        } else if self.cephfs_snap.unwrap_or_default() {
+            Some(SnapshotConfig::Cephfs)
+        } else {
+            None
+        }
+    }
 }
 
 /// Configuration for a restic target.
@@ -86,6 +743,15 @@ pub struct ResticTarget {
 
     /// Read passphrase from file
     pub passphrase_file: Option<String>,
+
+    /// Read passphrase from the output of a shell command
+    pub passphrase_command: Option<String>,
+
+    /// Read passphrase from a systemd credential
+    pub passphrase_credential: Option<String>,
+
+    /// Initialize the repository on first backup if it does not exist
+    pub init: Option<bool>,
 }
 
 /// S3 Credentials
@@ -95,15 +761,29 @@ pub struct S3Creds {
     pub secret_key: Option<String>,
     pub access_key_file: Option<String>,
     pub secret_key_file: Option<String>,
+    pub access_key_command: Option<String>,
+    pub secret_key_command: Option<String>,
+    pub access_key_credential: Option<String>,
+    pub secret_key_credential: Option<String>,
 }
 
 impl S3Creds {
     pub fn access_key(&self) -> Option<String> {
-        find_password(&self.access_key, &self.access_key_file)
+        find_password(
+            &self.access_key,
+            &self.access_key_file,
+            &self.access_key_command,
+            &self.access_key_credential,
+        )
     }
 
     pub fn secret_key(&self) -> Option<String> {
-        find_password(&self.secret_key, &self.secret_key_file)
+        find_password(
+            &self.secret_key,
+            &self.secret_key_file,
+            &self.secret_key_command,
+            &self.secret_key_credential,
+        )
     }
 }
 
@@ -155,6 +835,9 @@ pub struct ResticConfig {
 
     /// Host override
     pub host: Option<String>,
+
+    /// Back up to all targets concurrently
+    pub parallel_targets: Option<bool>,
 }
 /// Configuration for an individual restic forget job.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
@@ -244,6 +927,22 @@ pub struct ResticForget {
     pub repack_smaller_than: Option<String>,
 }
 
+/// Configuration for an individual restic check job.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ResticCheck {
+    /// Notifications (e.g. ntfy topics to notify after job)
+    pub ntfy: Option<Vec<String>>,
+
+    /// Restic repository targets
+    pub targets: Vec<String>,
+
+    /// read and verify this percentage of the repository data
+    pub read_data_subset: Option<u8>,
+
+    /// check a different subset of the data every day (UTC)
+    pub rotate_subset: Option<bool>,
+}
+
 // INPUT
 
 /// Local path input
@@ -258,13 +957,72 @@ pub struct LocalPath {
     /// Create CephFS snapshots before the backup.
     pub cephfs_snap: Option<bool>,
 
+    /// Create a read-only btrfs subvolume snapshot before the backup.
+    pub btrfs_snap: Option<bool>,
+
+    /// Create a ZFS snapshot of the dataset containing the path before the backup.
+    pub zfs_snap: Option<bool>,
+
+    /// Create a snapshot of this LVM volume (`vg/lv`) and mount it read-only before the backup.
+    pub lvm_snap: Option<String>,
+
+    /// Size of the LVM snapshot. Only needed for non thin volumes.
+    pub lvm_snap_size: Option<String>,
+
+    /// Additional mount options for the LVM snapshot (e.g. `nouuid` for XFS)
+    pub lvm_mount_options: Option<String>,
+
+    /// Snapshot to take before the backup. Replaces the `*_snap` options.
+    pub snapshot: Option<SnapshotConfig>,
+
     /// Bind mount to consistent path after snapshot creation
     pub same_path: Option<bool>,
 }
 
+impl LocalPath {
+    /// Snapshot taken before the backup, including the legacy `*_snap` options
+    pub fn snapshot_config(&self) -> Option<SnapshotConfig> {
+        if self.snapshot.is_some() {
+            self.snapshot.clone()
+        } else if self.cephfs_snap.unwrap_or_default() {
+            Some(SnapshotConfig::Cephfs)
+        } else if self.btrfs_snap.unwrap_or_default() {
+            Some(SnapshotConfig::Btrfs)
+        } else if self.zfs_snap.unwrap_or_default() {
+            Some(SnapshotConfig::Zfs)
+        } else {
+            self.lvm_snap.as_ref().map(|volume| SnapshotConfig::Lvm {
+                volume: volume.clone(),
+                size: self.lvm_snap_size.clone(),
+                mount_options: self.lvm_mount_options.clone(),
+            })
+        }
+    }
+
+    /// Consistent path the snapshot gets bind mounted to with `same_path`
+    pub fn consistent_path(&self) -> String {
+        format!("{MOUNT_DIR}{}", self.path.replace("/", "_"))
+    }
+
+    /// The path under which this input is stored inside a restic snapshot.
+    ///
+    /// Returns `None` if the path is not stable across runs (snapshot without `same_path`).
+    pub fn archived_path(&self) -> Option<String> {
+        if self.snapshot_config().is_some() {
+            if self.same_path.unwrap_or_default() {
+                Some(self.consistent_path())
+            } else {
+                None
+            }
+        } else {
+            Some(self.path.clone())
+        }
+    }
+}
+
 pub struct LocalPathRef {
     pub conf: LocalPath,
-    pub cephfs_snap_name: Option<String>,
+    pub snapshot: Option<Box<dyn SnapshotProvider>>,
     pub bind_mount_path: Option<String>,
 }
 
@@ -272,48 +1030,54 @@ impl LocalPathRef {
     pub fn from(conf: LocalPath) -> Self {
         Self {
             conf,
-            cephfs_snap_name: None,
+            snapshot: None,
             bind_mount_path: None,
         }
     }
 
-    pub fn get_target_path(&mut self) -> String {
+    /// Return the directory to back up, taking the snapshot first if one is configured
+    pub fn get_target_path(&mut self) -> Result<String, String> {
         if self.conf.ensure_exists.unwrap_or(true) {
             ensure_exists(&self.conf.path);
         }
 
-        if self.conf.cephfs_snap.unwrap_or_default() {
-            let (final_dir, snap_name) = cephfs_snap_create(&self.conf.path);
-            self.cephfs_snap_name = Some(snap_name);
+        let snapshot_dir = match self.conf.snapshot_config() {
+            Some(snapshot) => {
+                let mut provider = snapshot.provider(&self.conf.path);
+                let final_dir = provider.create()?;
+                self.snapshot = Some(provider);
+                Some(final_dir)
+            }
+            None => None,
+        };
 
+        if let Some(final_dir) = snapshot_dir {
             if self.conf.same_path.unwrap_or_default() {
-                let name = self.conf.path.replace("/", "_");
-                log::info!("Creating consistent path /bk/{}", name);
-                std::fs::create_dir_all(&format!("/bk/{name}")).unwrap();
-                let bind_mount_path = format!("/bk/{name}");
+                let bind_mount_path = self.conf.consistent_path();
+                log::info!("Creating consistent path {}", bind_mount_path);
+                std::fs::create_dir_all(&bind_mount_path).unwrap();
                 bind_mount(&final_dir, &bind_mount_path);
                 self.bind_mount_path = Some(bind_mount_path.clone());
-                return bind_mount_path;
+                return Ok(bind_mount_path);
             } else {
-                return final_dir;
+                return Ok(final_dir);
             }
         }
 
-        self.conf.path.clone()
+        Ok(self.conf.path.clone())
     }
 
-    pub fn cleanup(&self) {
-        if let Some(bmount) = &self.bind_mount_path {
+    pub fn cleanup(&mut self) {
+        if let Some(bmount) = self.bind_mount_path.take() {
             log::info!("Cleaning up mount {}", bmount);
             umount(&bmount);
         }
 
-        if let Some(snap) = &self.cephfs_snap_name {
-            log::info!(
-                "Cleaning up snapshot {}",
-                format!("{}@{}", self.conf.path, snap)
-            );
-            cephfs_snap_remove(&self.conf.path, &snap);
+        if let Some(mut snapshot) = self.snapshot.take() {
+            if let Some(path) = snapshot.path() {
+                log::info!("Cleaning up snapshot {}", path);
+            }
+            snapshot.remove();
         }
     }
 }
@@ -324,24 +1088,168 @@ impl Drop for LocalPathRef {
     }
 }
 
+/// Database dump input
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct DumpInput {
+    /// Database type
+    pub kind: DumpKind,
+
+    /// Database name. For SQLite the path to the database file.
+    pub database: String,
+
+    /// Database host
+    pub host: Option<String>,
+
+    /// Database port
+    pub port: Option<u16>,
+
+    /// Database user
+    pub user: Option<String>,
+
+    /// Database password
+    pub password: Option<String>,
+
+    /// File containing the database password
+    pub password_file: Option<String>,
+
+    /// Shell command printing the database password
+    pub password_command: Option<String>,
+
+    /// systemd credential containing the database password
+    pub password_credential: Option<String>,
+
+    /// Additional arguments for the dump command
+    pub args: Option<Vec<String>>,
+
+    /// File name of the dump inside the snapshot. Defaults to `<name>.sql`
+    pub filename: Option<String>,
+}
+
+/// Supported databases
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DumpKind {
+    /// PostgreSQL via `pg_dump`
+    Postgres,
+    /// MySQL / MariaDB via `mysqldump`
+    Mysql,
+    /// SQLite via `sqlite3 .dump`
+    Sqlite,
+}
+
+impl DumpInput {
+    /// Command line writing the dump to stdout
+    pub fn command(&self) -> Vec<String> {
+        let mut cmd: Vec<String> = match self.kind {
+            DumpKind::Postgres => vec!["pg_dump".into()],
+            DumpKind::Mysql => vec!["mysqldump".into(), "--single-transaction".into()],
+            DumpKind::Sqlite => vec!["sqlite3".into(), "-readonly".into()],
+        };
+
+        let flags = match self.kind {
+            DumpKind::Postgres => Some(("-h", "-p", "-U")),
+            DumpKind::Mysql => Some(("-h", "-P", "-u")),
+            DumpKind::Sqlite => None,
+        };
+
+        if let Some((host, port, user)) = flags {
+            for (flag, val) in [
+                (host, self.host.clone()),
+                (port, self.port.map(|x| x.to_string())),
+                (user, self.user.clone()),
+            ] {
+                if let Some(val) = val {
+                    cmd.push(flag.into());
+                    cmd.push(val);
+                }
+            }
+        }
+
+        cmd.extend(self.args.iter().flatten().cloned());
+        cmd.push(self.database.clone());
+
+        if self.kind == DumpKind::Sqlite {
+            cmd.push(".dump".into());
+        }
+
+        cmd
+    }
+
+    /// Environment passing the password to the dump command
+    pub fn env(&self) -> Vec<(String, String)> {
+        let var = match self.kind {
+            DumpKind::Postgres => "PGPASSWORD",
+            DumpKind::Mysql => "MYSQL_PWD",
+            DumpKind::Sqlite => return Vec::new(),
+        };
+
+        find_password(
+            &self.password,
+            &self.password_file,
+            &self.password_command,
+            &self.password_credential,
+        )
+        .map(|x| vec![(var.to_string(), x)])
+        .unwrap_or_default()
+    }
+
+    /// File name of the dump inside the snapshot
+    pub fn filename(&self, name: &str) -> String {
+        self.filename
+            .clone()
+            .unwrap_or_else(|| format!("{name}.sql"))
+    }
+}
+
+/// Command output input
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct CommandInput {
+    /// Command and its arguments. Its stdout is backed up.
+    pub command: Vec<String>,
+
+    /// File name of the output inside the snapshot
+    pub filename: String,
+}
+
 // Notification
 
-/// Ntfy configuration
+/// Notifier configuration
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct NtfyTarget {
     pub ntfy: Option<NtfyConfiguration>,
+
+    /// Email notifications
+    pub email: Option<EmailConfiguration>,
+
+    /// Webhook notifications
+    pub webhook: Option<WebhookConfiguration>,
 }
 
 impl NtfyTarget {
-    pub fn send_notification(&self, msg: &str) {
+    /// All configured notification backends
+    pub fn notifiers(&self) -> Vec<&dyn Notifier> {
+        let mut notifiers: Vec<&dyn Notifier> = Vec::new();
+
         if let Some(ntfy_conf) = &self.ntfy {
-            ntfy(
-                &ntfy_conf.host,
-                &ntfy_conf.topic,
-                ntfy_conf.auth.clone().map(|x| x.auth()),
-                msg,
-            )
-            .unwrap();
+            notifiers.push(ntfy_conf);
+        }
+
+        if let Some(email_conf) = &self.email {
+            notifiers.push(email_conf);
+        }
+
+        if let Some(webhook_conf) = &self.webhook {
+            notifiers.push(webhook_conf);
+        }
+
+        notifiers
+    }
+
+    pub fn send_notification(&self, msg: &Notification) {
+        for notifier in self.notifiers() {
+            if let Err(e) = notifier.notify(msg) {
+                log::error!("Sending notification failed: {e}");
+            }
         }
     }
 }
@@ -354,27 +1262,518 @@ pub struct NtfyConfiguration {
     pub auth: Option<NtfyAuth>,
 }
 
+impl Notifier for NtfyConfiguration {
+    fn notify(&self, msg: &Notification) -> Result<(), Box<dyn Error>> {
+        let auth = match &self.auth {
+            Some(auth) => Some(auth.auth().ok_or("no password provided for ntfy")?),
+            None => None,
+        };
+
+        ntfy(&self.host, &self.topic, auth, &msg.message)
+    }
+}
+
 /// Ntfy configuration
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct NtfyAuth {
     pub user: String,
     pub pass: Option<String>,
     pub pass_file: Option<String>,
+    pub pass_command: Option<String>,
+    pub pass_credential: Option<String>,
 }
 
 impl NtfyAuth {
-    pub fn auth(&self) -> (String, String) {
-        let pass = if let Some(pass) = &self.pass {
-            Some(pass.clone())
-        } else if let Some(pass) = &self.pass_file {
-            Some(std::fs::read_to_string(pass).expect("unable to read ntfy passfile"))
-        } else {
-            None
+    /// User and password, if a password could be found
+    pub fn auth(&self) -> Option<(String, String)> {
+        let pass = find_password(
+            &self.pass,
+            &self.pass_file,
+            &self.pass_command,
+            &self.pass_credential,
+        )?;
+
+        Some((self.user.clone(), pass))
+    }
+}
+
+/// Email (SMTP) configuration
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct EmailConfiguration {
+    /// SMTP server
+    pub host: String,
+
+    /// SMTP port (default 587 with STARTTLS, 25 without)
+    pub port: Option<u16>,
+
+    /// Upgrade the connection with STARTTLS (default true)
+    pub starttls: Option<bool>,
+
+    /// Sender address
+    pub from: String,
+
+    /// Recipient addresses
+    pub to: Vec<String>,
+
+    /// Mail subject
+    pub subject: Option<String>,
+
+    /// SMTP login
+    pub user: Option<String>,
+    pub pass: Option<String>,
+    pub pass_file: Option<String>,
+    pub pass_command: Option<String>,
+    pub pass_credential: Option<String>,
+}
+
+impl Notifier for EmailConfiguration {
+    fn notify(&self, msg: &Notification) -> Result<(), Box<dyn Error>> {
+        let starttls = self.starttls.unwrap_or(true);
+
+        let auth = match &self.user {
+            Some(user) => {
+                let pass = find_password(
+                    &self.pass,
+                    &self.pass_file,
+                    &self.pass_command,
+                    &self.pass_credential,
+                )
+                .ok_or("no password provided for SMTP login")?;
+                Some((user.clone(), pass))
+            }
+            None => None,
         };
 
-        (
-            self.user.clone(),
-            pass.expect("neither pass nor passfile provided"),
+        email(
+            &self.host,
+            self.port.unwrap_or(if starttls { 587 } else { 25 }),
+            starttls,
+            auth,
+            &self.from,
+            &self.to,
+            self.subject.as_deref().unwrap_or("bk backup notification"),
+            &msg.message,
+        )
+    }
+}
+
+/// Webhook configuration
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct WebhookConfiguration {
+    /// Webhook URL
+    pub url: String,
+
+    /// HTTP method (default POST)
+    pub method: Option<String>,
+
+    /// Additional HTTP headers
+    pub headers: Option<HashMap<String, String>>,
+
+    /// Body template. Placeholders like `{{status}}` get replaced with the job fields.
+    /// Defaults to the whole notification as JSON.
+    pub body: Option<String>,
+}
+
+impl Notifier for WebhookConfiguration {
+    fn notify(&self, msg: &Notification) -> Result<(), Box<dyn Error>> {
+        let body = match &self.body {
+            Some(template) => msg.render(template),
+            None => serde_json::to_string(msg)?,
+        };
+
+        webhook(
+            &self.url,
+            self.method.as_deref().unwrap_or("POST"),
+            &self.headers.clone().unwrap_or_default(),
+            body,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory for the files of a single test
+    fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("bk-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &std::path::Path, name: &str, content: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("*.toml", "a.toml"));
+        assert!(glob_match("*.toml", ".toml"));
+        assert!(!glob_match("*.toml", "a.toml.bak"));
+        assert!(glob_match("??-*.toml", "10-db.toml"));
+        assert!(!glob_match("??-*.toml", "1-db.toml"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**", "abc"));
+        assert!(!glob_match("?", ""));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exact2"));
+    }
+
+    #[test]
+    fn glob_backtracking() {
+        // The first `*` has to give back characters to let the rest match
+        assert!(glob_match("*a*b", "xaybab"));
+        assert!(glob_match("*ab", "aab"));
+        assert!(glob_match("a*b*c", "abbbcbc"));
+        assert!(!glob_match("a*b*c", "abbbcb"));
+        assert!(glob_match("*.d.toml", "x.d.d.toml"));
+        assert!(glob_match("*?", "a"));
+        assert!(!glob_match("*??", "a"));
+    }
+
+    #[test]
+    fn include_expansion_is_sorted() {
+        let dir = test_dir("expand");
+        let conf = write(&dir, "bk.toml", "");
+        std::fs::create_dir(dir.join("conf.d")).unwrap();
+        std::fs::create_dir(dir.join("conf.d/dir.toml")).unwrap();
+
+        for name in ["20-b.toml", "10-a.toml", "30-c.toml", "notes.txt"] {
+            write(&dir.join("conf.d"), name, "");
+        }
+
+        let files = expand_include(&conf, "conf.d/*.toml").unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|x| x.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(names, ["10-a.toml", "20-b.toml", "30-c.toml"]);
+
+        // Without wildcards the file is included as is, even if it does not exist yet
+        let files = expand_include(&conf, "extra.toml").unwrap();
+        assert_eq!(files, [dir.join("extra.toml").to_string_lossy()]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_merges_files() {
+        let dir = test_dir("merge");
+        write(
+            &dir,
+            "10-target.toml",
+            "[restic_target.local]\nrepo = \"/srv/restic\"\npassphrase = \"secret\"\n\n[[restic]]\nsrc = [\"home\"]\ntargets = [\"local\"]\n",
+        );
+        write(
+            &dir,
+            "20-path.toml",
+            "[path.etc]\npath = \"/etc\"\n\n[[restic]]\nsrc = [\"etc\"]\ntargets = [\"local\"]\n",
+        );
+        let conf = write(
+            &dir,
+            "bk.toml",
+            "include = [\"*-*.toml\"]\ndelay = 10\n\n[path.home]\npath = \"/home\"\n",
+        );
+
+        let conf = Config::from_path(&conf).unwrap();
+
+        let mut paths: Vec<_> = conf.path.unwrap().into_keys().collect();
+        paths.sort();
+        assert_eq!(paths, ["etc", "home"]);
+        assert_eq!(conf.delay, Some(10));
+
+        // Lists are concatenated in include order
+        let srcs: Vec<_> = conf.restic.unwrap().into_iter().map(|x| x.src).collect();
+        assert_eq!(srcs, [["home"], ["etc"]]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn nested_include_is_rejected() {
+        let dir = test_dir("nested");
+        write(&dir, "inner.toml", "");
+        let outer = write(&dir, "outer.toml", "include = [\"inner.toml\"]\n");
+        let conf = write(&dir, "bk.toml", "include = [\"outer.toml\"]\n");
+
+        let res = Config::from_path(&conf);
+        assert!(matches!(res, Err(ConfigError::NestedInclude { path }) if path == outer));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_map_entry() {
+        let dir = test_dir("duplicate");
+        let extra = write(&dir, "extra.toml", "[path.home]\npath = \"/srv/home\"\n");
+        let conf = write(
+            &dir,
+            "bk.toml",
+            "include = [\"extra.toml\"]\n\n[path.home]\npath = \"/home\"\n",
+        );
+
+        match Config::from_path(&conf) {
+            Err(ConfigError::Duplicate { key, first, second }) => {
+                assert_eq!(key, "path.home");
+                assert_eq!(first, conf);
+                assert_eq!(second, extra);
+            }
+            other => panic!("expected duplicate error, got {other:?}"),
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_value() {
+        let dir = test_dir("duplicate-value");
+        write(&dir, "a.toml", "delay = 10\n");
+        write(&dir, "b.toml", "delay = 20\n");
+        let conf = write(&dir, "bk.toml", "include = [\"?.toml\"]\n");
+
+        let res = Config::from_path(&conf);
+        assert!(matches!(res, Err(ConfigError::Duplicate { key, .. }) if key == "delay"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Validation errors of the config `content` as `path:line:column: message`
+    fn validation_errors(dir: &std::path::Path, content: &str) -> Vec<String> {
+        let conf = write(dir, "bk.toml", content);
+
+        match Config::from_path(&conf) {
+            Err(ConfigError::Invalid { errors, .. }) => {
+                errors.iter().map(|x| x.to_string()).collect()
+            }
+            other => panic!("expected validation errors, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_references() {
+        let dir = test_dir("unknown");
+        let errors = validation_errors(
+            &dir,
+            "[lock]\nntfy = [\"pager\"]\n\n[path.home]\npath = \"/home\"\n\n[restic_target.local]\nrepo = \"/srv/restic\"\npassphrase = \"secret\"\n\n[[restic]]\nsrc = [\"home\", \"etc\"]\ntargets = [\"local\", \"remote\"]\nntfy = [\"mail\"]\n",
+        );
+
+        let path = dir.join("bk.toml").to_string_lossy().to_string();
+        assert_eq!(
+            errors,
+            [
+                format!("{path}:2:9: unknown notifier `pager`"),
+                format!("{path}:12:16: unknown input `etc`"),
+                format!("{path}:13:21: unknown restic target `remote`"),
+                format!("{path}:14:9: unknown notifier `mail`"),
+            ]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn interpolated_references() {
+        // SAFETY: no other test reads or writes these variables
+        unsafe {
+            std::env::set_var("BK_TEST_TARGET", "local");
+            std::env::set_var("BK_TEST_OTHER_TARGET", "remote");
+        }
+
+        let dir = test_dir("interpolated-refs");
+        let valid = "[path.home]\npath = \"/home\"\n\n[restic_target.local]\nrepo = \"/srv/restic\"\npassphrase = \"secret\"\n\n[[restic]]\nsrc = [\"home\"]\ntargets = [\"${env:BK_TEST_TARGET}\"]\n";
+        let conf = write(&dir, "bk.toml", valid);
+        let conf = Config::from_path(&conf).unwrap();
+        assert_eq!(conf.restic.unwrap()[0].targets, ["local"]);
+
+        // Errors point at the placeholder but name the resolved value
+        let errors = validation_errors(
+            &dir,
+            &valid.replace("BK_TEST_TARGET", "BK_TEST_OTHER_TARGET"),
+        );
+        assert_eq!(
+            errors,
+            [format!(
+                "{}:10:12: unknown restic target `remote`",
+                dir.join("bk.toml").display()
+            )]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn dump(content: &str) -> DumpInput {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn postgres_dump_command() {
+        let input = dump(
+            "kind = \"postgres\"\ndatabase = \"app\"\nhost = \"db\"\nport = 5433\nuser = \"backup\"\nargs = [\"--no-owner\"]",
+        );
+        assert_eq!(
+            input.command(),
+            [
+                "pg_dump",
+                "-h",
+                "db",
+                "-p",
+                "5433",
+                "-U",
+                "backup",
+                "--no-owner",
+                "app"
+            ]
+        );
+
+        let input = dump("kind = \"postgres\"\ndatabase = \"app\"\nuser = \"backup\"");
+        assert_eq!(input.command(), ["pg_dump", "-U", "backup", "app"]);
+    }
+
+    #[test]
+    fn mysql_dump_command() {
+        let input = dump(
+            "kind = \"mysql\"\ndatabase = \"app\"\nhost = \"db\"\nport = 3307\nuser = \"backup\"\nargs = [\"--routines\", \"--events\"]",
+        );
+        assert_eq!(
+            input.command(),
+            [
+                "mysqldump",
+                "--single-transaction",
+                "-h",
+                "db",
+                "-P",
+                "3307",
+                "-u",
+                "backup",
+                "--routines",
+                "--events",
+                "app"
+            ]
+        );
+    }
+
+    #[test]
+    fn sqlite_dump_command() {
+        // Connection settings do not apply to SQLite
+        let input = dump(
+            "kind = \"sqlite\"\ndatabase = \"/srv/app.db\"\nhost = \"db\"\nuser = \"backup\"\nargs = [\"-bail\"]",
+        );
+        assert_eq!(
+            input.command(),
+            ["sqlite3", "-readonly", "-bail", "/srv/app.db", ".dump"]
+        );
+    }
+
+    #[test]
+    fn dump_password_env() {
+        let input = dump("kind = \"postgres\"\ndatabase = \"app\"\npassword = \"secret\"");
+        assert_eq!(
+            input.env(),
+            [("PGPASSWORD".to_string(), "secret".to_string())]
+        );
+
+        let input =
+            dump("kind = \"mysql\"\ndatabase = \"app\"\npassword_command = \"echo secret\"");
+        assert_eq!(
+            input.env(),
+            [("MYSQL_PWD".to_string(), "secret".to_string())]
+        );
+
+        let input = dump("kind = \"mysql\"\ndatabase = \"app\"");
+        assert!(input.env().is_empty());
+
+        let input = dump("kind = \"sqlite\"\ndatabase = \"app.db\"\npassword = \"secret\"");
+        assert!(input.env().is_empty());
+    }
+
+    #[test]
+    fn sqlite_dump() {
+        let sqlite3 = |args: &[&str]| std::process::Command::new("sqlite3").args(args).output();
+        if sqlite3(&["-version"]).is_err() {
+            eprintln!("sqlite3 is not installed, skipping");
+            return;
+        }
+
+        let dir = test_dir("sqlite");
+        let db = dir.join("app.db").to_string_lossy().to_string();
+        let res = sqlite3(&[&db, "CREATE TABLE t (x); INSERT INTO t VALUES (42);"]).unwrap();
+        assert!(res.status.success());
+
+        let input = dump(&format!("kind = \"sqlite\"\ndatabase = \"{db}\""));
+        let cmd = input.command();
+        let res = std::process::Command::new(&cmd[0])
+            .args(&cmd[1..])
+            .output()
+            .unwrap();
+        assert!(res.status.success());
+        assert!(String::from_utf8_lossy(&res.stdout).contains("INSERT INTO t VALUES(42);"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn interpolate_env() {
+        // SAFETY: no other test reads or writes this variable
+        unsafe { std::env::set_var("BK_TEST_INTERPOLATE", "secret") };
+
+        assert_eq!(
+            interpolate("a${env:BK_TEST_INTERPOLATE}b${env:BK_TEST_INTERPOLATE}").unwrap(),
+            "asecretbsecret"
+        );
+        assert_eq!(
+            interpolate("${env:BK_TEST_UNSET}").unwrap_err(),
+            "environment variable BK_TEST_UNSET is not set"
+        );
+    }
+
+    #[test]
+    fn interpolate_file() {
+        let dir = test_dir("interpolate");
+        let file = write(&dir, "secret", "secret\n");
+
+        assert_eq!(interpolate(&format!("${{file:{file}}}")).unwrap(), "secret");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            interpolate(&format!("${{file:{file}}}"))
+                .unwrap_err()
+                .starts_with(&format!("could not read {file}: "))
+        );
+    }
+
+    #[test]
+    fn interpolate_escape() {
+        assert_eq!(interpolate("$${env:HOME}").unwrap(), "${env:HOME}");
+        assert_eq!(interpolate("a$$${b").unwrap(), "a$${b");
+        assert_eq!(interpolate("$ {} $x").unwrap(), "$ {} $x");
+    }
+
+    #[test]
+    fn interpolate_errors() {
+        assert_eq!(interpolate("${env:HOME").unwrap_err(), "unterminated `${`");
+        assert_eq!(
+            interpolate("${vault:key}").unwrap_err(),
+            "unknown interpolation `${vault:key}`"
+        );
+        assert_eq!(
+            interpolate("${HOME}").unwrap_err(),
+            "unknown interpolation `${HOME}`"
+        );
+    }
+
+    #[test]
+    fn redacted_webhook() {
+        let conf: Config = toml::from_str(
+            "[ntfy.hook.webhook]\nurl = \"https://hooks.example.com/T0/secret\"\nheaders = { Authorization = \"Bearer secret\" }\n",
         )
+        .unwrap();
+
+        let ntfy = conf.redacted().ntfy.unwrap();
+        let webhook = ntfy["hook"].webhook.as_ref().unwrap();
+        assert_eq!(webhook.url, REDACTED);
+        assert_eq!(webhook.headers.as_ref().unwrap()["Authorization"], REDACTED);
     }
 }
@@ -0,0 +1,44 @@
+use yansi::{Color, Paint};
+
+use crate::{
+    args::InitCommand,
+    config::Config,
+    restic::{self, ResticRepo},
+};
+
+pub fn run_init(args: InitCommand) -> i32 {
+    let conf = match Config::from_path(&args.config) {
+        Ok(conf) => conf,
+        Err(e) => {
+            log::error!("{e}");
+            return 1;
+        }
+    };
+
+    let targets = conf.restic_target.unwrap_or_default();
+    let Some(target) = targets.get(&args.target) else {
+        log::error!("Unknown restic provider {}", args.target);
+        return 1;
+    };
+
+    let res = ResticRepo::new(target).and_then(|repo| restic::init_repo(&repo, false));
+
+    match res {
+        Ok(true) => {
+            log::info!("Initialized repository {}", target.repo);
+            0
+        }
+        Ok(false) => {
+            log::info!("Repository {} is already initialized", target.repo);
+            0
+        }
+        Err(e) => {
+            println!(
+                "{} Initializing {} failed: {e}",
+                "Error:".paint(Color::Red),
+                args.target
+            );
+            1
+        }
+    }
+}
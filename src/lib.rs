@@ -1,10 +1,25 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    os::unix::process::ExitStatusExt,
+};
+
 use yansi::{Color, Paint};
 
 pub mod args;
 pub mod backup;
+pub mod check;
+pub mod cleanup;
 pub mod config;
+pub mod init;
+pub mod lock;
+pub mod metrics;
 pub mod notify;
+pub mod report;
 pub mod restic;
+pub mod restore;
+pub mod signal;
+pub mod snapshot;
+pub mod snapshots;
 
 pub fn run_command(cmd: &[&str], env: Option<Vec<(String, String)>>) -> (String, String, i32) {
     println!("--> {} ", cmd.join(" ").paint(Color::Blue));
@@ -23,8 +38,11 @@ pub fn run_command(cmd: &[&str], env: Option<Vec<(String, String)>>) -> (String,
     }
 
     let child = cmd_setup.spawn().unwrap();
+    let pid = child.id();
+    signal::register_child(pid);
 
     let status = child.wait_with_output().unwrap();
+    signal::unregister_child(pid);
     if !status.status.success() {
         println!(
             "{} Command {} returned with non zero exit code.",
@@ -40,5 +58,128 @@ pub fn run_command(cmd: &[&str], env: Option<Vec<(String, String)>>) -> (String,
         eprintln!("{stderr}");
     }
 
-    (output, stderr, status.status.code().unwrap())
+    (output, stderr, exit_code(status.status))
+}
+
+/// Run a command and capture its stdout instead of passing it through.
+pub fn run_command_output(
+    cmd: &[&str],
+    env: Option<Vec<(String, String)>>,
+) -> (String, String, i32) {
+    println!("--> {} ", cmd.join(" ").paint(Color::Blue));
+
+    let mut cmd_setup = std::process::Command::new(cmd[0]);
+    let mut cmd_setup = cmd_setup.args(cmd.iter().skip(1).collect::<Vec<_>>());
+
+    cmd_setup = cmd_setup
+        .stdout(std::process::Stdio::piped())
+        .stdin(std::process::Stdio::inherit());
+
+    if let Some(pw) = env {
+        for e in pw {
+            cmd_setup = cmd_setup.env(e.0, e.1);
+        }
+    }
+
+    let child = cmd_setup.spawn().unwrap();
+    let pid = child.id();
+    signal::register_child(pid);
+
+    let status = child.wait_with_output().unwrap();
+    signal::unregister_child(pid);
+    if !status.status.success() {
+        println!(
+            "{} Command {} returned with non zero exit code.",
+            "Error:".paint(Color::Red),
+            cmd.join(" ")
+        );
+    }
+
+    let output = String::from_utf8(status.stdout).unwrap();
+    let stderr = String::from_utf8(status.stderr).unwrap();
+
+    (output, stderr, exit_code(status.status))
+}
+
+/// Run a command with every line of its output prefixed with `prefix`.
+///
+/// Used for commands running in parallel. Stdout is returned instead of printed if `capture` is set.
+pub fn run_command_prefixed(
+    cmd: &[&str],
+    env: Option<Vec<(String, String)>>,
+    prefix: &str,
+    capture: bool,
+) -> (String, String, i32) {
+    println!("{prefix} --> {} ", cmd.join(" ").paint(Color::Blue));
+
+    let mut cmd_setup = std::process::Command::new(cmd[0]);
+    cmd_setup
+        .args(&cmd[1..])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+
+    if let Some(pw) = env {
+        cmd_setup.envs(pw);
+    }
+
+    let mut child = cmd_setup.spawn().unwrap();
+    let pid = child.id();
+    signal::register_child(pid);
+
+    let stderr = prefix_lines(child.stderr.take().unwrap(), prefix, true);
+    let mut output = String::new();
+
+    if capture {
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+    } else {
+        prefix_lines(child.stdout.take().unwrap(), prefix, false)
+            .join()
+            .unwrap();
+    }
+
+    stderr.join().unwrap();
+    let status = child.wait().unwrap();
+    signal::unregister_child(pid);
+
+    if !status.success() {
+        println!(
+            "{prefix} {} Command {} returned with non zero exit code.",
+            "Error:".paint(Color::Red),
+            cmd.join(" ")
+        );
+    }
+
+    (output, String::new(), exit_code(status))
+}
+
+/// Print every line read from `reader` with `prefix` in a background thread
+pub fn prefix_lines(
+    reader: impl Read + Send + 'static,
+    prefix: &str,
+    stderr: bool,
+) -> std::thread::JoinHandle<()> {
+    let prefix = prefix.to_string();
+
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            if stderr {
+                eprintln!("{prefix} {line}");
+            } else {
+                println!("{prefix} {line}");
+            }
+        }
+    })
+}
+
+/// Exit code of a process. Processes killed by a signal get 128 + signal number.
+pub fn exit_code(status: std::process::ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or_default())
 }
@@ -0,0 +1,95 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+    os::fd::AsRawFd,
+};
+
+use crate::{
+    config::{LockConfig, LockMode},
+    signal,
+};
+
+/// Default path of the run lock
+pub const DEFAULT_LOCK_PATH: &str = "/run/bk.lock";
+
+/// Exclusive lock on the lock file. Released when dropped.
+pub struct RunLock {
+    _file: File,
+}
+
+/// Result of trying to take the run lock
+pub enum LockResult {
+    /// The lock is held by this process
+    Acquired(RunLock),
+    /// Another run holds the lock. Contains the PID and start time written by it.
+    Busy(String),
+    /// The lock file could not be opened
+    Error(std::io::Error),
+}
+
+/// Take the run lock according to `conf`.
+///
+/// With [`LockMode::Wait`] this blocks until the lock is free or a signal is received.
+pub fn acquire(conf: &LockConfig) -> LockResult {
+    let path = conf.path.as_deref().unwrap_or(DEFAULT_LOCK_PATH);
+
+    let mut file = match File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(e) => return LockResult::Error(e),
+    };
+
+    let mut logged = false;
+
+    while !try_lock(&file) {
+        let holder = holder(&mut file);
+
+        if conf.mode.unwrap_or_default() != LockMode::Wait || signal::interrupted() {
+            return LockResult::Busy(holder);
+        }
+
+        if !logged {
+            log::info!("Waiting for run lock {path} held by {holder}");
+            logged = true;
+        }
+
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+
+    // Only the holder writes, so readers see the current run
+    let info = format!(
+        "PID {} started {}\n",
+        std::process::id(),
+        chrono::Local::now().to_rfc3339()
+    );
+
+    if let Err(e) = file
+        .set_len(0)
+        .and_then(|_| file.rewind())
+        .and_then(|_| file.write_all(info.as_bytes()))
+    {
+        log::warn!("Could not write run lock {path}: {e}");
+    }
+
+    LockResult::Acquired(RunLock { _file: file })
+}
+
+fn try_lock(file: &File) -> bool {
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+/// PID and start time of the run holding the lock
+fn holder(file: &mut File) -> String {
+    let mut info = String::new();
+    let _ = file.rewind().and_then(|_| file.read_to_string(&mut info));
+
+    match info.trim() {
+        "" => "another run".to_string(),
+        info => info.to_string(),
+    }
+}
@@ -1,8 +1,9 @@
-use bk::{args::BkArgs, backup::run_backup, config::Config};
+use bk::{
+    args::BkArgs, backup::run_backup, check::run_check_config, cleanup::run_cleanup,
+    config::Config, init::run_init, restore::run_restore, snapshots::list_snapshots,
+};
 use schemars::schema_for;
 
-// TODO : add basic ctrl+c support for ending bk tasks instead of everything and ensure cleanups
-
 fn main() {
     // Enable log output by default
     if std::env::var("RUST_LOG").is_err() {
@@ -10,17 +11,46 @@ fn main() {
     }
     env_logger::init();
 
+    // Let running tasks finish their cleanup on Ctrl+C / SIGTERM
+    bk::signal::install();
+
     let args: BkArgs = argh::from_env();
     match args.cmd {
         bk::args::BkCommand::Show(show_command) => {
-            let conf = Config::from_path(&show_command.config);
+            let conf = match Config::from_path(&show_command.config) {
+                Ok(conf) => conf,
+                Err(e) => {
+                    log::error!("{e}");
+                    std::process::exit(1);
+                }
+            };
             // TODO : better representation
-            println!("{conf:#?}");
+            println!("{:#?}", conf.redacted());
         }
         bk::args::BkCommand::Run(run_command) => {
             let state = run_backup(run_command);
             std::process::exit(state);
         }
+        bk::args::BkCommand::Restore(restore_command) => {
+            let state = run_restore(restore_command);
+            std::process::exit(state);
+        }
+        bk::args::BkCommand::Snapshots(snapshots_command) => {
+            let state = list_snapshots(snapshots_command);
+            std::process::exit(state);
+        }
+        bk::args::BkCommand::Init(init_command) => {
+            let state = run_init(init_command);
+            std::process::exit(state);
+        }
+        bk::args::BkCommand::Cleanup(cleanup_command) => {
+            let state = run_cleanup(cleanup_command);
+            std::process::exit(state);
+        }
+        bk::args::BkCommand::CheckConfig(check_command) => {
+            let state = run_check_config(check_command);
+            std::process::exit(state);
+        }
         bk::args::BkCommand::ConfigSchema(_) => {
             let schema = schema_for!(bk::config::Config);
             println!("{}", serde_json::to_string_pretty(&schema).unwrap());
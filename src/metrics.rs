@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+use crate::report::{JobReport, RunReport};
+
+/// Gauges written to the textfile with their help text
+const METRICS: [(&str, &str); 5] = [
+    (
+        "bk_job_status",
+        "Result of the last job run (1 = success, 0 = failure)",
+    ),
+    (
+        "bk_last_run_timestamp",
+        "Unix timestamp of the last job run",
+    ),
+    (
+        "bk_last_success_timestamp",
+        "Unix timestamp of the last successful job run",
+    ),
+    (
+        "bk_last_duration_seconds",
+        "Duration of the last job run in seconds",
+    ),
+    (
+        "bk_bytes_added",
+        "Bytes added to the repository by the last backup",
+    ),
+];
+
+/// Metric name -> labels -> value
+type Series = BTreeMap<String, BTreeMap<String, f64>>;
+
+/// Write the metrics of `report` in the Prometheus text format for node_exporter's textfile collector.
+///
+/// Series of jobs which did not run this time are kept from the previous file.
+pub fn write_textfile(path: &str, report: &RunReport) -> std::io::Result<()> {
+    let mut series = std::fs::read_to_string(path)
+        .map(|x| parse_textfile(&x))
+        .unwrap_or_default();
+
+    for job in &report.jobs {
+        update_series(&mut series, job);
+    }
+
+    log::info!("Writing metrics to {path}");
+
+    // node_exporter may read the file at any time so replace it atomically
+    let tmp = format!("{path}.tmp");
+    std::fs::write(&tmp, render_textfile(&series))?;
+    std::fs::rename(&tmp, path)
+}
+
+fn render_textfile(series: &Series) -> String {
+    let mut out = String::new();
+
+    for (name, help) in METRICS {
+        let Some(values) = series.get(name) else {
+            continue;
+        };
+
+        out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} gauge\n"));
+        for (labels, value) in values {
+            out.push_str(&format!("{name}{{{labels}}} {value}\n"));
+        }
+    }
+
+    out
+}
+
+fn update_series(series: &mut Series, job: &JobReport) {
+    let labels = format!(
+        "job=\"{}\",target=\"{}\"",
+        job.job,
+        escape_label(&job.target)
+    );
+
+    let mut set = |name: &str, value: f64| {
+        series
+            .entry(name.to_string())
+            .or_default()
+            .insert(labels.clone(), value);
+    };
+
+    set("bk_job_status", if job.success { 1.0 } else { 0.0 });
+    set("bk_last_run_timestamp", job.end.timestamp() as f64);
+    set("bk_last_duration_seconds", job.duration);
+
+    if job.success {
+        set("bk_last_success_timestamp", job.end.timestamp() as f64);
+    }
+
+    if let Some(summary) = &job.summary {
+        set("bk_bytes_added", summary.data_added as f64);
+    }
+}
+
+fn parse_textfile(content: &str) -> Series {
+    let mut series = Series::new();
+
+    for line in content.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let Some((metric, value)) = line.rsplit_once(' ') else {
+            continue;
+        };
+
+        let Some((name, labels)) = metric.split_once('{') else {
+            continue;
+        };
+
+        let (Some(labels), Ok(value)) = (labels.strip_suffix('}'), value.parse()) else {
+            continue;
+        };
+
+        series
+            .entry(name.to_string())
+            .or_default()
+            .insert(labels.to_string(), value);
+    }
+
+    series
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{report::JobKind, restic::BackupSummary};
+    use chrono::{TimeZone, Utc};
+
+    fn job(kind: JobKind, target: &str, success: bool, end: i64) -> JobReport {
+        let end = Utc.timestamp_opt(end, 0).unwrap();
+        JobReport {
+            job: kind,
+            target: target.to_string(),
+            sources: Vec::new(),
+            start: end,
+            end,
+            duration: 1.5,
+            success,
+            error: None,
+            exit_code: None,
+            summary: success.then(|| BackupSummary {
+                data_added: 2048,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut series = Series::new();
+        update_series(
+            &mut series,
+            &job(JobKind::Restic, "/srv/restic [pg]", true, 1000),
+        );
+        update_series(
+            &mut series,
+            &job(JobKind::ResticCheck, "s3:bucket/\"quoted\"", false, 2000),
+        );
+
+        let text = render_textfile(&series);
+
+        assert!(text.contains(
+            "# HELP bk_job_status Result of the last job run (1 = success, 0 = failure)\n# TYPE bk_job_status gauge\n"
+        ));
+        assert!(text.contains("bk_job_status{job=\"restic\",target=\"/srv/restic [pg]\"} 1\n"));
+        assert!(text.contains(
+            "bk_job_status{job=\"restic_check\",target=\"s3:bucket/\\\"quoted\\\"\"} 0\n"
+        ));
+        assert_eq!(parse_textfile(&text), series);
+    }
+
+    #[test]
+    fn old_series_are_kept() {
+        let path =
+            std::env::temp_dir().join(format!("bk-test-metrics-{}.prom", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut report = RunReport::new("bk.toml", false);
+        report.jobs = vec![
+            job(JobKind::Restic, "/srv/restic [pg]", true, 1000),
+            job(JobKind::Restic, "/srv/other", true, 1000),
+        ];
+        write_textfile(path, &report).unwrap();
+
+        // Only one target runs, and fails
+        report.jobs = vec![job(JobKind::Restic, "/srv/restic [pg]", false, 5000)];
+        write_textfile(path, &report).unwrap();
+
+        let series = parse_textfile(&std::fs::read_to_string(path).unwrap());
+        std::fs::remove_file(path).unwrap();
+
+        let value =
+            |name: &str, target: &str| series[name][&format!("job=\"restic\",target=\"{target}\"")];
+
+        assert_eq!(value("bk_job_status", "/srv/restic [pg]"), 0.0);
+        assert_eq!(value("bk_last_run_timestamp", "/srv/restic [pg]"), 5000.0);
+        // The last success stays at the earlier run
+        assert_eq!(
+            value("bk_last_success_timestamp", "/srv/restic [pg]"),
+            1000.0
+        );
+        assert_eq!(value("bk_bytes_added", "/srv/restic [pg]"), 2048.0);
+
+        assert_eq!(value("bk_job_status", "/srv/other"), 1.0);
+        assert_eq!(value("bk_last_run_timestamp", "/srv/other"), 1000.0);
+    }
+}
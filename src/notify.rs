@@ -1,5 +1,94 @@
-use reqwest::blocking::Client;
-use std::error::Error;
+use openssl::ssl::{SslConnector, SslMethod};
+use reqwest::{Method, blocking::Client};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+/// A notification backend
+pub trait Notifier {
+    /// Send `msg` to this notifier
+    fn notify(&self, msg: &Notification) -> Result<(), Box<dyn Error>>;
+}
+
+/// Notification about a finished job
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    /// Human readable message
+    pub message: String,
+    /// `success` or `failure`
+    pub status: String,
+    /// Job kind (e.g. `restic`)
+    pub job: String,
+    /// Restic repository or rsync destination
+    pub target: String,
+    pub sources: Vec<String>,
+    pub error: Option<String>,
+    /// Duration in seconds
+    pub duration: f64,
+}
+
+impl Notification {
+    /// Notification not tied to a job
+    pub fn new(message: String, success: bool) -> Self {
+        Self {
+            message,
+            status: if success { "success" } else { "failure" }.to_string(),
+            job: "run".to_string(),
+            target: String::new(),
+            sources: Vec::new(),
+            error: None,
+            duration: 0.0,
+        }
+    }
+
+    /// Replace `{{field}}` placeholders in `template` with the JSON escaped field values.
+    ///
+    /// Placeholders inside the substituted values are kept as they are. Unknown placeholders too.
+    pub fn render(&self, template: &str) -> String {
+        let field = |key: &str| match key {
+            "message" => Some(self.message.clone()),
+            "status" => Some(self.status.clone()),
+            "job" => Some(self.job.clone()),
+            "target" => Some(self.target.clone()),
+            "sources" => Some(self.sources.join(", ")),
+            "error" => Some(self.error.clone().unwrap_or_default()),
+            "duration" => Some(self.duration.to_string()),
+            _ => None,
+        };
+
+        let mut out = String::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let value = rest
+                .find("}}")
+                .and_then(|end| Some((end, field(&rest[2..end])?)));
+
+            match value {
+                Some((end, value)) => {
+                    let escaped = serde_json::to_string(&value).unwrap();
+                    out.push_str(&escaped[1..escaped.len() - 1]);
+                    rest = &rest[end + 2..];
+                }
+                None => {
+                    out.push_str("{{");
+                    rest = &rest[2..];
+                }
+            }
+        }
+
+        out.push_str(rest);
+        out
+    }
+}
 
 /// Sends a POST request to the specified API endpoint with a given body.
 fn post_api(url: &str, body: &str, auth: Option<(String, String)>) -> Result<(), Box<dyn Error>> {
@@ -16,6 +105,32 @@ fn post_api(url: &str, body: &str, auth: Option<(String, String)>) -> Result<(),
     Ok(())
 }
 
+/// Sends a request to a webhook.
+pub fn webhook(
+    url: &str,
+    method: &str,
+    headers: &HashMap<String, String>,
+    body: String,
+) -> Result<(), Box<dyn Error>> {
+    let client = Client::new();
+    let method = Method::from_bytes(method.to_uppercase().as_bytes())?;
+    let mut request = client.request(method.clone(), url).body(body);
+
+    for (key, value) in headers {
+        request = request.header(key, value);
+    }
+
+    let response = request.send()?;
+
+    log::info!("Webhook {method} {} => {}", url, response.status());
+
+    if !response.status().is_success() {
+        return Err(format!("webhook returned {}", response.status()).into());
+    }
+
+    Ok(())
+}
+
 /// Sends a message to an `ntfy.sh` topic.
 pub fn ntfy(
     host: &str,
@@ -26,3 +141,339 @@ pub fn ntfy(
     let url = format!("{host}/{topic}");
     post_api(&url, message, auth)
 }
+
+/// Sends a message as email over SMTP.
+///
+/// Credentials are only sent over STARTTLS.
+#[allow(clippy::too_many_arguments)]
+pub fn email(
+    host: &str,
+    port: u16,
+    starttls: bool,
+    auth: Option<(String, String)>,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    message: &str,
+) -> Result<(), Box<dyn Error>> {
+    if auth.is_some() && !starttls {
+        return Err("refusing to send SMTP credentials without STARTTLS".into());
+    }
+
+    let stream = TcpStream::connect((host, port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+
+    let mut smtp = Smtp::new(stream);
+    smtp.expect(220)?;
+    smtp.command("EHLO bk", 250)?;
+
+    if starttls {
+        smtp.command("STARTTLS", 220)?;
+        let tls = tls_connector()?.connect(host, smtp.into_inner())?;
+
+        let mut smtp = Smtp::new(tls);
+        smtp.command("EHLO bk", 250)?;
+        smtp.send_mail(auth, from, to, subject, message)
+    } else {
+        smtp.send_mail(auth, from, to, subject, message)
+    }
+}
+
+/// TLS connector trusting the system CA store.
+///
+/// The vendored OpenSSL only looks in its own build directory for certificates.
+fn tls_connector() -> Result<SslConnector, Box<dyn Error>> {
+    let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+    let certs = openssl_probe::probe();
+
+    if certs.cert_file.is_some() || certs.cert_dir.is_some() {
+        builder.load_verify_locations(certs.cert_file.as_deref(), certs.cert_dir.as_deref())?;
+    }
+
+    Ok(builder.build())
+}
+
+/// Minimal SMTP client
+struct Smtp<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Smtp<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    /// Read a (multiline) reply and fail if the code is not `code`
+    fn expect(&mut self, code: u16) -> Result<String, Box<dyn Error>> {
+        let mut reply = String::new();
+
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line)? == 0 {
+                return Err("SMTP connection closed".into());
+            }
+
+            reply.push_str(&line);
+
+            // Multiline replies continue with `<code>-`
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+
+        if !reply.starts_with(&code.to_string()) {
+            return Err(format!("unexpected SMTP reply: {}", reply.trim()).into());
+        }
+
+        Ok(reply)
+    }
+
+    fn command(&mut self, cmd: &str, code: u16) -> Result<String, Box<dyn Error>> {
+        self.stream
+            .get_mut()
+            .write_all(format!("{cmd}\r\n").as_bytes())?;
+        self.expect(code)
+    }
+
+    fn send_mail(
+        &mut self,
+        auth: Option<(String, String)>,
+        from: &str,
+        to: &[String],
+        subject: &str,
+        message: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some((user, pass)) = auth {
+            let token = openssl::base64::encode_block(format!("\0{user}\0{pass}").as_bytes());
+            self.command(&format!("AUTH PLAIN {token}"), 235)?;
+        }
+
+        self.command(&format!("MAIL FROM:<{from}>"), 250)?;
+        for rcpt in to {
+            self.command(&format!("RCPT TO:<{rcpt}>"), 250)?;
+        }
+
+        self.command("DATA", 354)?;
+
+        let subject = if subject.is_ascii() {
+            subject.to_string()
+        } else {
+            format!(
+                "=?UTF-8?B?{}?=",
+                openssl::base64::encode_block(subject.as_bytes())
+            )
+        };
+
+        let mut data = format!(
+            "From: {from}\r\nTo: {}\r\nSubject: {subject}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            to.join(", "),
+            chrono::Utc::now().to_rfc2822()
+        );
+
+        for line in message.lines() {
+            // Dot stuffing
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+
+        data.push_str(".\r\n");
+        self.stream.get_mut().write_all(data.as_bytes())?;
+        self.expect(250)?;
+
+        self.command("QUIT", 221)?;
+
+        log::info!("SMTP mail to {} sent", to.join(", "));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn notification() -> Notification {
+        let mut msg = Notification::new("🚨 Backup failed: \"x\"\nline 2".to_string(), false);
+        msg.job = "restic".to_string();
+        msg.target = "/srv/restic".to_string();
+        msg.sources = vec!["home".to_string(), "etc".to_string()];
+        msg.error = Some("Fatal Error".to_string());
+        msg.duration = 1.5;
+        msg
+    }
+
+    #[test]
+    fn render_fields() {
+        let body = notification().render(
+            r#"{"text": "{{message}}", "status": "{{status}}", "job": "{{job}}", "target": "{{target}}", "sources": "{{sources}}", "error": "{{error}}", "took": {{duration}}}"#,
+        );
+
+        assert_eq!(
+            body,
+            r#"{"text": "🚨 Backup failed: \"x\"\nline 2", "status": "failure", "job": "restic", "target": "/srv/restic", "sources": "home, etc", "error": "Fatal Error", "took": 1.5}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()["text"],
+            "🚨 Backup failed: \"x\"\nline 2"
+        );
+    }
+
+    #[test]
+    fn render_does_not_expand_values() {
+        let mut msg = notification();
+        msg.message = "message with {{status}} and {{error}}".to_string();
+        msg.target = "{{job}}".to_string();
+
+        assert_eq!(
+            msg.render("{{message}} {{target}} {{status}}"),
+            "message with {{status}} and {{error}} {{job}} failure"
+        );
+    }
+
+    #[test]
+    fn render_unknown_placeholders() {
+        assert_eq!(
+            notification().render("{{unknown}}{{status}} {{ status }} {{status"),
+            "{{unknown}}failure {{ status }} {{status"
+        );
+    }
+
+    /// Stream replaying scripted server replies and recording the client's commands
+    struct MockStream {
+        replies: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(replies: &str) -> Self {
+            Self {
+                replies: Cursor::new(replies.as_bytes().to_vec()),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn smtp_dialogue() {
+        let replies = "220 mail ESMTP\r\n\
+                       250-mail\r\n250-AUTH PLAIN\r\n250 8BITMIME\r\n\
+                       235 authenticated\r\n\
+                       250 sender ok\r\n\
+                       250 rcpt ok\r\n250 rcpt ok\r\n\
+                       354 go ahead\r\n\
+                       250 queued\r\n\
+                       221 bye\r\n";
+
+        let mut smtp = Smtp::new(MockStream::new(replies));
+        smtp.expect(220).unwrap();
+        let ehlo = smtp.command("EHLO bk", 250).unwrap();
+        assert!(ehlo.contains("AUTH PLAIN"));
+
+        smtp.send_mail(
+            Some(("user".to_string(), "pass".to_string())),
+            "bk@example.com",
+            &["a@example.com".to_string(), "b@example.com".to_string()],
+            "Backup",
+            "first line\n.hidden\n..two dots",
+        )
+        .unwrap();
+
+        let sent = String::from_utf8(smtp.into_inner().sent).unwrap();
+        let token = openssl::base64::encode_block(b"\0user\0pass");
+
+        assert!(sent.starts_with(&format!(
+            "EHLO bk\r\nAUTH PLAIN {token}\r\nMAIL FROM:<bk@example.com>\r\n\
+             RCPT TO:<a@example.com>\r\nRCPT TO:<b@example.com>\r\nDATA\r\n"
+        )));
+        assert!(sent.contains("Subject: Backup\r\n"));
+        assert!(sent.contains("\r\n\r\nfirst line\r\n..hidden\r\n...two dots\r\n.\r\n"));
+        assert!(sent.ends_with(".\r\nQUIT\r\n"));
+    }
+
+    #[test]
+    fn smtp_without_auth() {
+        let replies = "250 ok\r\n250 ok\r\n354 go ahead\r\n250 queued\r\n221 bye\r\n";
+
+        let mut smtp = Smtp::new(MockStream::new(replies));
+        smtp.send_mail(
+            None,
+            "bk@example.com",
+            &["a@example.com".to_string()],
+            "Ünïcode",
+            "hi",
+        )
+        .unwrap();
+
+        let sent = String::from_utf8(smtp.into_inner().sent).unwrap();
+
+        assert!(sent.starts_with("MAIL FROM:<bk@example.com>\r\n"));
+        assert!(!sent.contains("AUTH"));
+        assert!(sent.contains("Subject: =?UTF-8?B?"));
+    }
+
+    #[test]
+    fn smtp_rejected_reply() {
+        let mut smtp = Smtp::new(MockStream::new("250 ok\r\n550 no such user\r\n"));
+
+        let err = smtp
+            .send_mail(
+                None,
+                "bk@example.com",
+                &["x@example.com".to_string()],
+                "s",
+                "m",
+            )
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "unexpected SMTP reply: 550 no such user");
+    }
+
+    #[test]
+    fn smtp_closed_connection() {
+        let mut smtp = Smtp::new(MockStream::new("250-first line\r\n"));
+        assert!(smtp.expect(250).is_err());
+    }
+
+    #[test]
+    fn auth_requires_starttls() {
+        let err = email(
+            "localhost",
+            25,
+            false,
+            Some(("user".to_string(), "pass".to_string())),
+            "bk@example.com",
+            &["a@example.com".to_string()],
+            "s",
+            "m",
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("without STARTTLS"));
+    }
+}
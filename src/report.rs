@@ -0,0 +1,155 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    notify::Notification,
+    restic::{BackupSummary, ResticError, TargetResult},
+};
+
+/// Kind of a job within a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Rsync,
+    Restic,
+    ResticForget,
+    ResticCheck,
+}
+
+impl std::fmt::Display for JobKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            JobKind::Rsync => "rsync",
+            JobKind::Restic => "restic",
+            JobKind::ResticForget => "restic_forget",
+            JobKind::ResticCheck => "restic_check",
+        })
+    }
+}
+
+/// Machine readable report of a single `bk run`
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    /// Config file used for the run
+    pub config: String,
+    pub dry_run: bool,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Duration in seconds
+    pub duration: f64,
+    /// Exit code of the run
+    pub status: i32,
+    pub jobs: Vec<JobReport>,
+}
+
+/// Report of a single job on a single target
+#[derive(Debug, Clone, Serialize)]
+pub struct JobReport {
+    pub job: JobKind,
+    /// Restic repository or rsync destination
+    pub target: String,
+    pub sources: Vec<String>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Duration in seconds
+    pub duration: f64,
+    pub success: bool,
+    /// Restic error
+    pub error: Option<ResticError>,
+    /// Exit code of non restic jobs
+    pub exit_code: Option<i32>,
+    /// Restic backup summary
+    pub summary: Option<BackupSummary>,
+}
+
+impl JobReport {
+    pub fn from_restic(job: JobKind, target: &str, sources: &[String], res: &TargetResult) -> Self {
+        Self {
+            job,
+            target: target.to_string(),
+            sources: sources.to_vec(),
+            start: res.start,
+            end: res.end,
+            duration: duration_secs(res.start, res.end),
+            success: res.result.is_ok(),
+            error: res.result.err(),
+            exit_code: None,
+            summary: res.summary.clone(),
+        }
+    }
+
+    pub fn from_exit_code(
+        job: JobKind,
+        target: &str,
+        sources: &[String],
+        start: DateTime<Utc>,
+        code: i32,
+    ) -> Self {
+        let end = Utc::now();
+        Self {
+            job,
+            target: target.to_string(),
+            sources: sources.to_vec(),
+            start,
+            end,
+            duration: duration_secs(start, end),
+            success: code == 0,
+            error: None,
+            exit_code: Some(code),
+            summary: None,
+        }
+    }
+}
+
+impl JobReport {
+    /// Notification about this job with the human readable `message`
+    pub fn notification(&self, message: String) -> Notification {
+        let error = match (&self.error, self.exit_code) {
+            (Some(e), _) => Some(e.to_string()),
+            (None, Some(code)) if code != 0 => Some(format!("exit code {code}")),
+            _ => None,
+        };
+
+        Notification {
+            message,
+            status: if self.success { "success" } else { "failure" }.to_string(),
+            job: self.job.to_string(),
+            target: self.target.clone(),
+            sources: self.sources.clone(),
+            error,
+            duration: self.duration,
+        }
+    }
+}
+
+impl RunReport {
+    pub fn new(config: &str, dry_run: bool) -> Self {
+        let start = Utc::now();
+        Self {
+            config: config.to_string(),
+            dry_run,
+            start,
+            end: start,
+            duration: 0.0,
+            status: 0,
+            jobs: Vec::new(),
+        }
+    }
+
+    /// Mark the run as finished with exit code `status`
+    pub fn finish(&mut self, status: i32) {
+        self.end = Utc::now();
+        self.duration = duration_secs(self.start, self.end);
+        self.status = status;
+    }
+
+    /// Write the report as JSON to `path`
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        log::info!("Writing run report to {path}");
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+fn duration_secs(start: DateTime<Utc>, end: DateTime<Utc>) -> f64 {
+    (end - start).num_milliseconds() as f64 / 1000.0
+}
@@ -1,10 +1,21 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    collections::HashMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use yansi::{Color, Paint};
 
 use crate::{
-    config::{LocalPath, LocalPathRef, ResticConfig, ResticForget, ResticTarget},
-    run_command,
+    config::{
+        CommandInput, DumpInput, LocalPath, LocalPathRef, ResticCheck, ResticConfig, ResticForget,
+        ResticTarget,
+    },
+    run_command, run_command_output, run_command_prefixed, signal,
 };
 
 pub fn bind_mount(src: &str, dst: &str) {
@@ -17,464 +28,955 @@ pub fn umount(mount: &str) {
 
 const NO_S3_CREDS: &str = "no s3 credentials provided";
 
+/// `Config::from_path` rejects configs referencing unknown inputs or targets
+const VALIDATED: &str = "reference checked by config validation";
+
 pub fn create_archive(
     conf: &ResticConfig,
     path_provider: HashMap<String, LocalPath>,
+    dump_provider: HashMap<String, DumpInput>,
+    command_provider: HashMap<String, CommandInput>,
     target_provider: HashMap<String, ResticTarget>,
+    max_parallel: Option<usize>,
     dry: bool,
-) -> HashMap<String, Result<(), ResticError>> {
-    let mut paths: Vec<_> = conf
-        .src
-        .iter()
-        .map(|x| {
-            if let Some(pp) = path_provider.get(x) {
-                let pp = LocalPathRef::from(pp.clone());
-                return pp;
-            } else {
-                log::error!("Unknown path provider {x}");
-                std::process::exit(1);
-            }
-        })
-        .collect();
-
-    let mut dirs = Vec::new();
-
-    for path in &mut paths {
-        dirs.push(path.get_target_path());
+) -> HashMap<String, TargetResult> {
+    let mut paths = Vec::new();
+    let mut dumps = Vec::new();
+    let mut commands = Vec::new();
+
+    for x in &conf.src {
+        if let Some(pp) = path_provider.get(x) {
+            paths.push(LocalPathRef::from(pp.clone()));
+        } else if let Some(dump) = dump_provider.get(x) {
+            dumps.push((x, dump));
+        } else {
+            commands.push((x, command_provider.get(x).expect(VALIDATED)));
+        }
     }
 
     let targets: Vec<_> = conf
         .targets
         .iter()
-        .map(|x| {
-            if let Some(pp) = target_provider.get(x) {
-                return pp;
-            } else {
-                log::error!("Unknown restic provider {x}");
-                std::process::exit(1);
-            }
-        })
+        .map(|x| (x, target_provider.get(x).expect(VALIDATED)))
         .collect();
 
-    let mut targets_results = HashMap::new();
-
-    for repo in targets {
-        log::info!(
-            "Running backup for {} on {}",
-            conf.src.join(",").paint(Color::Yellow),
-            repo.repo.paint(Color::Yellow)
-        );
-
-        let mut cmd = vec!["restic", "backup"];
-
-        let empty = Vec::new();
-        for ex in conf.exclude.as_ref().unwrap_or(&empty) {
-            cmd.push("--exclude");
-            cmd.push(ex);
-        }
+    let mut dirs = Vec::new();
 
-        for ex in conf.exclude_if_present.as_ref().unwrap_or(&empty) {
-            cmd.push("--exclude-if-present");
-            cmd.push(ex);
+    for path in &mut paths {
+        match path.get_target_path() {
+            Ok(dir) => dirs.push(dir),
+            Err(e) => {
+                // Snapshots taken so far are removed when `paths` is dropped
+                log::error!(
+                    "{} Could not create snapshot: {e}",
+                    "Error:".paint(Color::Red)
+                );
+                let start = Utc::now();
+                return targets
+                    .into_iter()
+                    .map(|(_, x)| {
+                        (
+                            x.repo.clone(),
+                            TargetResult::finish(start, Err(ResticError::Fatal)),
+                        )
+                    })
+                    .collect();
+            }
         }
+    }
 
-        if conf.one_file_system.unwrap_or_default() {
-            cmd.push("--one-file-system");
+    let workers = if conf.parallel_targets.unwrap_or_default() {
+        max_parallel
+            .unwrap_or(targets.len())
+            .clamp(1, targets.len().max(1))
+    } else {
+        1
+    };
+
+    let sources = ArchiveSources {
+        dirs: &dirs,
+        dumps: &dumps,
+        commands: &commands,
+    };
+
+    // Targets share the snapshots and bind mounts taken above
+    let next = AtomicUsize::new(0);
+    let targets_results = Mutex::new(HashMap::new());
+
+    std::thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| {
+                while let Some((name, target)) = targets.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    if signal::interrupted() {
+                        break;
+                    }
+
+                    let res = backup_target(conf, name, target, &sources, workers > 1, dry);
+                    targets_results.lock().unwrap().extend(res);
+                }
+            });
         }
+    });
 
-        let c = conf.concurrency.unwrap_or(2).to_string();
-        cmd.push("--read-concurrency");
-        cmd.push(&c);
+    targets_results.into_inner().unwrap()
+}
 
-        let tags = conf.tags.clone().unwrap_or_default();
+/// Sources of a backup job shared by all targets
+struct ArchiveSources<'a> {
+    dirs: &'a [String],
+    dumps: &'a [(&'a String, &'a DumpInput)],
+    commands: &'a [(&'a String, &'a CommandInput)],
+}
 
-        for t in &tags {
-            cmd.push("--tag");
-            cmd.push(&t);
+/// Back up `sources` to the target `target_name`. Output is prefixed with the name if `prefixed`.
+fn backup_target(
+    conf: &ResticConfig,
+    target_name: &str,
+    target: &ResticTarget,
+    sources: &ArchiveSources,
+    prefixed: bool,
+    dry: bool,
+) -> Vec<(String, TargetResult)> {
+    let mut targets_results = Vec::new();
+    let start = Utc::now();
+
+    log::info!(
+        "Running backup for {} on {}",
+        conf.src.join(",").paint(Color::Yellow),
+        target.repo.paint(Color::Yellow)
+    );
+
+    let repo = match ResticRepo::new(target) {
+        Ok(repo) if prefixed => repo.with_prefix(format!("[{target_name}]")),
+        Ok(repo) => repo,
+        Err(e) => {
+            targets_results.push((target.repo.clone(), TargetResult::finish(start, Err(e))));
+            return targets_results;
         }
+    };
 
-        if conf.reread.unwrap_or_default() {
-            cmd.push("--force");
-        }
+    if target.init.unwrap_or_default()
+        && let Err(e) = init_repo(&repo, dry)
+    {
+        targets_results.push((target.repo.clone(), TargetResult::finish(start, Err(e))));
+        return targets_results;
+    }
 
-        if conf.exclude_caches.unwrap_or_default() {
-            cmd.push("--exclude-caches");
-        }
+    let mut cmd = repo.invocation(ResticCommand::Backup);
 
-        if dry {
-            cmd.push("--dry-run");
-        }
+    for ex in conf.exclude.iter().flatten() {
+        cmd.option("--exclude", Some(ex));
+    }
 
-        let zstd10 = "auto".to_string();
-        let comp = conf.compression.as_ref().unwrap_or(&zstd10);
-        cmd.push("--compression");
-        cmd.push(comp);
+    for ex in conf.exclude_if_present.iter().flatten() {
+        cmd.option("--exclude-if-present", Some(ex));
+    }
 
-        if conf.quiet.unwrap_or_default() {
-            cmd.push("--quiet");
-            cmd.push("--json");
-        }
+    cmd.flag(
+        "--one-file-system",
+        conf.one_file_system.unwrap_or_default(),
+    )
+    .option("--read-concurrency", Some(conf.concurrency.unwrap_or(2)));
 
-        if let Some(host) = &conf.host {
-            cmd.push("--host");
-            cmd.push(host);
-        }
+    for t in conf.tags.iter().flatten() {
+        cmd.option("--tag", Some(t));
+    }
 
-        cmd.push("-r");
-        cmd.push(&repo.repo);
+    cmd.flag("--force", conf.reread.unwrap_or_default())
+        .flag("--exclude-caches", conf.exclude_caches.unwrap_or_default())
+        .flag("--dry-run", dry)
+        .option(
+            "--compression",
+            Some(conf.compression.as_deref().unwrap_or("auto")),
+        );
 
-        cmd.extend(dirs.iter().map(|x| x.as_str()));
+    let json = conf.quiet.unwrap_or_default();
 
-        let mut env = Vec::new();
+    if json {
+        cmd.arg("--quiet").arg("--json");
+    }
 
-        if let Some(passphrase) = &repo.passphrase {
-            env.push(("RESTIC_PASSWORD".to_string(), passphrase.clone()));
-        } else if let Some(pass_file) = &repo.passphrase_file {
-            let passphrase =
-                std::fs::read_to_string(pass_file).expect("Could not read passphrase file");
-            env.push(("RESTIC_PASSWORD".to_string(), passphrase));
-        } else {
-            log::error!(
-                "Neither passphrase nor passphrase file provided for {}",
-                repo.repo
-            );
-            targets_results.insert(repo.repo.clone(), Err(ResticError::Fatal));
-        }
+    cmd.option("--host", conf.host.as_ref());
 
-        if let Some(s3) = &repo.s3 {
-            env.push((
-                "AWS_ACCESS_KEY_ID".to_string(),
-                s3.access_key().expect(NO_S3_CREDS).clone(),
-            ));
-            env.push((
-                "AWS_SECRET_ACCESS_KEY".to_string(),
-                s3.secret_key().expect(NO_S3_CREDS).clone(),
-            ));
-        }
+    if !sources.dirs.is_empty() {
+        let mut cmd = cmd.clone();
+        cmd.args(sources.dirs);
+        targets_results.push((target.repo.clone(), run_backup(&cmd, start, json)));
+    }
 
-        let mut ssh_opt = None;
-
-        if let Some(ssh) = &repo.ssh {
-            let remote = repo.repo.trim_start_matches("sftp:");
-            let hostpart = remote.split(':').collect::<Vec<_>>();
-            let hostpart = hostpart.first().unwrap();
-            let (user, host) = hostpart.split_once('@').unwrap();
-            let ssh_cmd = format!(
-                "ssh -i {} {} -o StrictHostKeyChecking=no {user}@{host} -s sftp",
-                ssh.identity,
-                if let Some(p) = ssh.port {
-                    format!("-p {p}")
-                } else {
-                    String::new()
-                }
-            );
-            ssh_opt = Some(format!("sftp.command={ssh_cmd}"));
+    // Every dump becomes its own snapshot
+    for (name, dump) in sources.dumps {
+        if signal::interrupted() {
+            break;
         }
 
-        if let Some(ssh_opt) = &ssh_opt {
-            cmd.push("-o");
-            cmd.push(ssh_opt);
-        }
+        let start = Utc::now();
 
-        let res = run_command(&cmd, Some(env));
+        // restic does not save a snapshot if the dump fails
+        let mut cmd = cmd.clone();
+        cmd.envs(dump.env())
+            .arg("--stdin-from-command")
+            .option("--stdin-filename", Some(dump.filename(name)))
+            .arg("--")
+            .args(&dump.command());
 
-        if res.2 == 0 {
-            targets_results.insert(repo.repo.clone(), Ok(()));
-        } else {
-            let err = ResticError::from_code(res.2).unwrap();
-            targets_results.insert(repo.repo.clone(), Err(err));
+        targets_results.push((
+            format!("{} [{name}]", target.repo),
+            run_backup(&cmd, start, json),
+        ));
+    }
+
+    // restic fails the backup if the command exits non zero
+    for (name, command) in sources.commands {
+        if signal::interrupted() {
+            break;
         }
+
+        let start = Utc::now();
+
+        let mut cmd = cmd.clone();
+        cmd.arg("--stdin-from-command")
+            .option("--stdin-filename", Some(&command.filename))
+            .arg("--")
+            .args(&command.command);
+
+        targets_results.push((
+            format!("{} [{name}]", target.repo),
+            run_backup(&cmd, start, json),
+        ));
     }
 
     targets_results
 }
 
+/// Run a backup invocation, parsing the JSON output if `json` is set
+fn run_backup(cmd: &ResticInvocation, start: DateTime<Utc>, json: bool) -> TargetResult {
+    if json {
+        let (res, output) = cmd.capture();
+        TargetResult::finish(start, res).with_summary(handle_backup_output(&output))
+    } else {
+        TargetResult::finish(start, cmd.run())
+    }
+}
+
 pub fn forget_archive(
     conf: &ResticForget,
     target_provider: HashMap<String, ResticTarget>,
     dry: bool,
-) -> HashMap<String, Result<(), ResticError>> {
+) -> HashMap<String, TargetResult> {
     let targets: Vec<_> = conf
         .targets
         .iter()
-        .map(|x| {
-            if let Some(pp) = target_provider.get(x) {
-                return pp;
-            } else {
-                log::error!("Unknown restic provider {x}");
-                std::process::exit(1);
-            }
-        })
+        .map(|x| target_provider.get(x).expect(VALIDATED))
         .collect();
 
     let mut targets_results = HashMap::new();
 
-    for repo in targets {
+    for target in targets {
+        if signal::interrupted() {
+            break;
+        }
+
+        let start = Utc::now();
+
         log::info!(
             "Running backup forget for {}",
-            repo.repo.paint(Color::Yellow)
+            target.repo.paint(Color::Yellow)
         );
 
-        let mut cmd = vec!["restic", "forget"];
-
-        if conf.compact.unwrap_or_default() {
-            cmd.push("--compact");
+        let repo = match ResticRepo::new(target) {
+            Ok(repo) => repo,
+            Err(e) => {
+                targets_results.insert(target.repo.clone(), TargetResult::finish(start, Err(e)));
+                continue;
+            }
+        };
+
+        let mut cmd = repo.invocation(ResticCommand::Forget);
+
+        cmd.flag("--compact", conf.compact.unwrap_or_default())
+            .option("--keep-last", conf.keep_last)
+            .option("--keep-hourly", conf.keep_hourly)
+            .option("--keep-daily", conf.keep_daily)
+            .option("--keep-weekly", conf.keep_weekly)
+            .option("--keep-monthly", conf.keep_monthly)
+            .option("--keep-yearly", conf.keep_yearly)
+            .option("--keep-within", conf.keep_within)
+            .option("--keep-within-hourly", conf.keep_within_hourly)
+            .option("--keep-within-daily", conf.keep_within_daily)
+            .option("--keep-within-weekly", conf.keep_within_weekly)
+            .option("--keep-within-monthly", conf.keep_within_monthly)
+            .option("--keep-within-yearly", conf.keep_within_yearly);
+
+        for val in conf.keep_tag.iter().flatten() {
+            cmd.option("--keep-tag", Some(val));
         }
 
-        let istr = IntStrings::new();
+        cmd.flag(
+            "--unsafe-allow-remove-all",
+            conf.unsafe_allow_remove_all.unwrap_or_default(),
+        );
 
-        if let Some(val) = &conf.keep_last {
-            cmd.push("--keep-last");
-            cmd.push(istr.format(*val));
+        for val in conf.host.iter().flatten() {
+            cmd.option("--host", Some(val));
         }
 
-        if let Some(val) = &conf.keep_hourly {
-            cmd.push("--keep-hourly");
-            cmd.push(istr.format(*val));
+        for val in conf.path.iter().flatten() {
+            cmd.option("--path", Some(val));
         }
 
-        if let Some(val) = &conf.keep_daily {
-            cmd.push("--keep-daily");
-            cmd.push(istr.format(*val));
-        }
+        cmd.option("--group-by", conf.group_by.as_ref())
+            .flag("--prune", conf.prune.unwrap_or_default())
+            .option("--max-unused", conf.max_unused.as_ref())
+            .option("--max-repack-size", conf.max_repack_size.as_ref())
+            .flag(
+                "--repack-cacheable-only",
+                conf.repack_cacheable_only.unwrap_or_default(),
+            )
+            .flag("--repack-small", conf.repack_small.unwrap_or_default())
+            .flag(
+                "--repack-uncompressed",
+                conf.repack_uncompressed.unwrap_or_default(),
+            )
+            .option("--repack-smaller-than", conf.repack_smaller_than.as_ref())
+            .flag("--dry-run", dry);
+
+        targets_results.insert(target.repo.clone(), TargetResult::finish(start, cmd.run()));
+    }
 
-        if let Some(val) = &conf.keep_weekly {
-            cmd.push("--keep-weekly");
-            cmd.push(istr.format(*val));
-        }
+    targets_results
+}
 
-        if let Some(val) = &conf.keep_monthly {
-            cmd.push("--keep-monthly");
-            cmd.push(istr.format(*val));
-        }
+pub fn check_archive(
+    conf: &ResticCheck,
+    target_provider: HashMap<String, ResticTarget>,
+) -> HashMap<String, TargetResult> {
+    let targets: Vec<_> = conf
+        .targets
+        .iter()
+        .map(|x| target_provider.get(x).expect(VALIDATED))
+        .collect();
 
-        if let Some(val) = &conf.keep_yearly {
-            cmd.push("--keep-yearly");
-            cmd.push(istr.format(*val));
+    let subset = match conf.read_data_subset {
+        Some(percent) if percent == 0 || percent > 100 => {
+            log::error!("Invalid read_data_subset {percent}%. Must be between 1 and 100.");
+            let start = Utc::now();
+            return targets
+                .into_iter()
+                .map(|x| {
+                    (
+                        x.repo.clone(),
+                        TargetResult::finish(start, Err(ResticError::Fatal)),
+                    )
+                })
+                .collect();
         }
-
-        if let Some(val) = &conf.keep_within {
-            cmd.push("--keep-within");
-            cmd.push(istr.format(*val));
+        Some(percent) if conf.rotate_subset.unwrap_or_default() => {
+            // Split the repository into parts of `percent` size and check the next one every day
+            let parts = 100_u64.div_ceil(percent as u64);
+            let day = Utc::now().timestamp() as u64 / 86400;
+            Some(format!("{}/{parts}", day % parts + 1))
         }
+        Some(percent) => Some(format!("{percent}%")),
+        None => None,
+    };
 
-        if let Some(val) = &conf.keep_within_hourly {
-            cmd.push("--keep-within-hourly");
-            cmd.push(istr.format(*val));
-        }
+    let mut targets_results = HashMap::new();
 
-        if let Some(val) = &conf.keep_within_daily {
-            cmd.push("--keep-within-daily");
-            cmd.push(istr.format(*val));
+    for target in targets {
+        if signal::interrupted() {
+            break;
         }
 
-        if let Some(val) = &conf.keep_within_weekly {
-            cmd.push("--keep-within-weekly");
-            cmd.push(istr.format(*val));
-        }
+        let start = Utc::now();
 
-        if let Some(val) = &conf.keep_within_monthly {
-            cmd.push("--keep-within-monthly");
-            cmd.push(istr.format(*val));
-        }
+        log::info!(
+            "Running repository check for {}",
+            target.repo.paint(Color::Yellow)
+        );
 
-        if let Some(val) = &conf.keep_within_yearly {
-            cmd.push("--keep-within-yearly");
-            cmd.push(istr.format(*val));
-        }
+        let repo = match ResticRepo::new(target) {
+            Ok(repo) => repo,
+            Err(e) => {
+                targets_results.insert(target.repo.clone(), TargetResult::finish(start, Err(e)));
+                continue;
+            }
+        };
 
-        if let Some(val) = &conf.keep_daily {
-            cmd.push("--keep-daily");
-            cmd.push(istr.format(*val));
-        }
+        let mut cmd = repo.invocation(ResticCommand::Check);
+        cmd.option("--read-data-subset", subset.as_ref());
 
-        if let Some(val) = &conf.keep_weekly {
-            cmd.push("--keep-weekly");
-            cmd.push(istr.format(*val));
-        }
+        targets_results.insert(target.repo.clone(), TargetResult::finish(start, cmd.run()));
+    }
 
-        if let Some(val) = &conf.keep_monthly {
-            cmd.push("--keep-monthly");
-            cmd.push(istr.format(*val));
+    targets_results
+}
+
+/// Outcome of a restic operation on a single target
+#[derive(Debug, Clone)]
+pub struct TargetResult {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub result: Result<(), ResticError>,
+    /// Backup summary if restic reported one
+    pub summary: Option<BackupSummary>,
+}
+
+impl TargetResult {
+    /// Result of an operation started at `start` which just finished
+    pub fn finish(start: DateTime<Utc>, result: Result<(), ResticError>) -> Self {
+        Self {
+            start,
+            end: Utc::now(),
+            result,
+            summary: None,
         }
+    }
 
-        if let Some(val) = &conf.keep_tag {
-            for val in val {
-                cmd.push("--keep-tag");
-                cmd.push(val.as_str());
-            }
+    pub fn with_summary(mut self, summary: Option<BackupSummary>) -> Self {
+        self.summary = summary;
+        self
+    }
+}
+
+/// Message of the `restic backup --json` output stream
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "message_type", rename_all = "snake_case")]
+pub enum BackupMessage {
+    Status(BackupStatus),
+    Summary(BackupSummary),
+    Error(BackupError),
+    ExitError(BackupExitError),
+    #[serde(other)]
+    Unknown,
+}
+
+/// Progress of a running backup
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BackupStatus {
+    pub seconds_elapsed: u64,
+    pub seconds_remaining: u64,
+    pub percent_done: f64,
+    pub total_files: u64,
+    pub files_done: u64,
+    pub total_bytes: u64,
+    pub bytes_done: u64,
+    pub error_count: u64,
+    pub current_files: Vec<String>,
+}
+
+/// Summary of a finished backup
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BackupSummary {
+    pub files_new: u64,
+    pub files_changed: u64,
+    pub files_unmodified: u64,
+    pub dirs_new: u64,
+    pub dirs_changed: u64,
+    pub dirs_unmodified: u64,
+    pub data_blobs: i64,
+    pub tree_blobs: i64,
+    pub data_added: u64,
+    pub data_added_packed: u64,
+    pub total_files_processed: u64,
+    pub total_bytes_processed: u64,
+    pub total_duration: f64,
+    /// Created snapshot. Not set in dry run mode.
+    pub snapshot_id: Option<String>,
+}
+
+impl std::fmt::Display for BackupSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} new and {} changed files",
+            format_bytes(self.data_added),
+            self.files_new,
+            self.files_changed
+        )?;
+
+        if let Some(id) = &self.snapshot_id {
+            write!(f, ", snapshot {}", id.chars().take(8).collect::<String>())?;
         }
 
-        if conf.unsafe_allow_remove_all.unwrap_or_default() {
-            cmd.push("--unsafe-allow-remove-all");
+        Ok(())
+    }
+}
+
+/// Error while reading a single item
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BackupError {
+    pub error: serde_json::Value,
+    pub during: String,
+    pub item: String,
+}
+
+impl BackupError {
+    pub fn message(&self) -> String {
+        match &self.error {
+            serde_json::Value::String(msg) => msg.clone(),
+            serde_json::Value::Object(obj) => obj
+                .get("message")
+                .and_then(|x| x.as_str())
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| self.error.to_string()),
+            other => other.to_string(),
         }
+    }
+}
+
+/// Fatal error terminating restic
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BackupExitError {
+    pub code: i32,
+    pub message: String,
+}
 
-        if let Some(val) = &conf.host {
-            for val in val {
-                cmd.push("--host");
-                cmd.push(val.as_str());
+/// Parse the JSON message stream of `restic backup --json`. Lines which are not valid messages are skipped.
+pub fn parse_backup_output(output: &str) -> Vec<BackupMessage> {
+    output
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| x.starts_with('{'))
+        .filter_map(|x| match serde_json::from_str(x) {
+            Ok(msg) => Some(msg),
+            Err(e) => {
+                log::debug!("Could not parse restic message {x}: {e}");
+                None
             }
-        }
+        })
+        .collect()
+}
+
+/// Log errors of a `restic backup --json` run and return its summary
+fn handle_backup_output(output: &str) -> Option<BackupSummary> {
+    let mut summary = None;
 
-        if let Some(val) = &conf.path {
-            for val in val {
-                cmd.push("--path");
-                cmd.push(val.as_str());
+    for msg in parse_backup_output(output) {
+        match msg {
+            BackupMessage::Summary(s) => {
+                log::info!("Backup summary: {s}");
+                summary = Some(s);
             }
+            BackupMessage::Error(e) => {
+                log::warn!("Error during {} of {}: {}", e.during, e.item, e.message());
+            }
+            BackupMessage::ExitError(e) => {
+                log::error!("restic exited with code {}: {}", e.code, e.message);
+            }
+            BackupMessage::Status(_) | BackupMessage::Unknown => {}
         }
+    }
 
-        if let Some(val) = &conf.group_by {
-            cmd.push("--group-by");
-            cmd.push(val.as_str());
-        }
+    summary
+}
 
-        if conf.prune.unwrap_or_default() {
-            cmd.push("--prune");
-        }
+/// Human readable byte size
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
 
-        if let Some(val) = &conf.max_unused {
-            cmd.push("--max-unused");
-            cmd.push(val.as_str());
-        }
+    let mut value = bytes as f64;
+    let mut unit = 0;
 
-        if let Some(val) = &conf.max_repack_size {
-            cmd.push("--max-repack-size");
-            cmd.push(val.as_str());
-        }
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
 
-        if conf.repack_cacheable_only.unwrap_or_default() {
-            cmd.push("--repack-cacheable-only");
-        }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
 
-        if conf.repack_small.unwrap_or_default() {
-            cmd.push("--repack-small");
-        }
+/// Check if the repository exists
+pub fn is_initialized(repo: &ResticRepo) -> Result<bool, ResticError> {
+    let mut cmd = repo.invocation(ResticCommand::Cat);
+    cmd.arg("config");
 
-        if conf.repack_uncompressed.unwrap_or_default() {
-            cmd.push("--repack-uncompressed");
-        }
+    match cmd.output() {
+        Ok(_) => Ok(true),
+        Err(ResticError::RepositoryUnavailable) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
 
-        if let Some(val) = &conf.repack_smaller_than {
-            cmd.push("--repack-smaller-than");
-            cmd.push(val.as_str());
-        }
+/// Initialize the repository if it does not exist yet.
+///
+/// Returns whether the repository was initialized.
+pub fn init_repo(repo: &ResticRepo, dry: bool) -> Result<bool, ResticError> {
+    if is_initialized(repo)? {
+        return Ok(false);
+    }
+
+    if dry {
+        log::warn!(
+            "Repository {} does not exist and would be initialized",
+            repo.repo
+        );
+        return Ok(false);
+    }
+
+    log::info!("Initializing repository {}", repo.repo.paint(Color::Yellow));
+    repo.invocation(ResticCommand::Init).run()?;
+
+    Ok(true)
+}
+
+pub fn restore_archive(
+    target: &ResticTarget,
+    snapshot: &str,
+    dest: &str,
+    include: &[String],
+    exclude: &[String],
+    path: Option<&str>,
+    dry: bool,
+) -> Result<(), ResticError> {
+    log::info!(
+        "Restoring {} from {} to {}",
+        snapshot.paint(Color::Yellow),
+        target.repo.paint(Color::Yellow),
+        dest.paint(Color::Yellow)
+    );
+
+    let repo = ResticRepo::new(target)?;
+    let mut cmd = repo.invocation(ResticCommand::Restore);
+
+    match path {
+        Some(path) => cmd.arg(format!("{snapshot}:{path}")),
+        None => cmd.arg(snapshot),
+    };
+
+    // Select the latest snapshot containing the requested path
+    cmd.option("--target", Some(dest)).option("--path", path);
+
+    for inc in include {
+        cmd.option("--include", Some(inc));
+    }
+
+    for ex in exclude {
+        cmd.option("--exclude", Some(ex));
+    }
+
+    cmd.flag("--dry-run", dry).run()
+}
+
+/// A snapshot as reported by `restic snapshots --json`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub short_id: Option<String>,
+    pub time: String,
+    pub hostname: String,
+    pub paths: Vec<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+pub fn list_snapshots(
+    target: &ResticTarget,
+    host: &[String],
+    tag: &[String],
+    path: &[String],
+) -> Result<Vec<Snapshot>, ResticError> {
+    let repo = ResticRepo::new(target)?;
+    let mut cmd = repo.invocation(ResticCommand::Snapshots);
+
+    cmd.arg("--json");
 
-        if dry {
-            cmd.push("--dry-run");
+    for val in host {
+        cmd.option("--host", Some(val));
+    }
+
+    for val in tag {
+        cmd.option("--tag", Some(val));
+    }
+
+    for val in path {
+        cmd.option("--path", Some(val));
+    }
+
+    let output = cmd.output()?;
+
+    serde_json::from_str(&output).map_err(|e| {
+        log::error!("Could not parse snapshots of {}: {e}", target.repo);
+        ResticError::Fatal
+    })
+}
+
+/// Restic subcommands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResticCommand {
+    Backup,
+    Forget,
+    Check,
+    Snapshots,
+    Init,
+    Restore,
+    Unlock,
+    Cat,
+}
+
+impl ResticCommand {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResticCommand::Backup => "backup",
+            ResticCommand::Forget => "forget",
+            ResticCommand::Check => "check",
+            ResticCommand::Snapshots => "snapshots",
+            ResticCommand::Init => "init",
+            ResticCommand::Restore => "restore",
+            ResticCommand::Unlock => "unlock",
+            ResticCommand::Cat => "cat",
         }
+    }
+}
 
-        cmd.push("-r");
-        cmd.push(&repo.repo);
+/// A restic repository with resolved credentials and SSH options.
+///
+/// Every restic operation should be built from this so they share the same auth handling.
+#[derive(Debug, Clone)]
+pub struct ResticRepo {
+    /// Restic repository URL
+    pub repo: String,
+    env: Vec<(String, String)>,
+    ssh_opt: Option<String>,
+    prefix: Option<String>,
+}
 
+impl ResticRepo {
+    pub fn new(target: &ResticTarget) -> Result<Self, ResticError> {
         let mut env = Vec::new();
 
-        let passphrase = find_password(&repo.passphrase, &repo.passphrase_file);
-        match passphrase {
+        match find_password(
+            &target.passphrase,
+            &target.passphrase_file,
+            &target.passphrase_command,
+            &target.passphrase_credential,
+        ) {
             Some(passphrase) => {
-                env.push(("RESTIC_PASSWORD".to_string(), passphrase.clone()));
+                env.push(("RESTIC_PASSWORD".to_string(), passphrase));
             }
             None => {
-                log::error!(
-                    "Neither passphrase nor passphrase file provided for {}",
-                    repo.repo
-                );
-                targets_results.insert(repo.repo.clone(), Err(ResticError::Fatal));
+                log::error!("No passphrase available for {}", target.repo);
+                return Err(ResticError::Fatal);
             }
         }
 
-        if let Some(s3) = &repo.s3 {
-            env.push((
-                "AWS_ACCESS_KEY_ID".to_string(),
-                s3.access_key().expect(NO_S3_CREDS).clone(),
-            ));
-            env.push((
-                "AWS_SECRET_ACCESS_KEY".to_string(),
-                s3.secret_key().expect(NO_S3_CREDS).clone(),
-            ));
-        }
+        if let Some(s3) = &target.s3 {
+            let (Some(access_key), Some(secret_key)) = (s3.access_key(), s3.secret_key()) else {
+                log::error!("{NO_S3_CREDS} for {}", target.repo);
+                return Err(ResticError::Fatal);
+            };
 
-        let mut ssh_opt = None;
-
-        if let Some(ssh) = &repo.ssh {
-            let remote = repo.repo.trim_start_matches("sftp:");
-            let hostpart = remote.split(':').collect::<Vec<_>>();
-            let hostpart = hostpart.first().unwrap();
-            let (user, host) = hostpart.split_once('@').unwrap();
-            let ssh_cmd = format!(
-                "ssh -i {} {} -o StrictHostKeyChecking=no {user}@{host} -s sftp",
-                ssh.identity,
-                if let Some(p) = ssh.port {
-                    format!("-p {p}")
-                } else {
-                    String::new()
-                }
-            );
-            ssh_opt = Some(format!("sftp.command={ssh_cmd}"));
+            env.push(("AWS_ACCESS_KEY_ID".to_string(), access_key));
+            env.push(("AWS_SECRET_ACCESS_KEY".to_string(), secret_key));
         }
 
-        if let Some(ssh_opt) = &ssh_opt {
-            cmd.push("-o");
-            cmd.push(ssh_opt);
-        }
+        Ok(Self {
+            repo: target.repo.clone(),
+            env,
+            ssh_opt: ssh_option(target),
+            prefix: None,
+        })
+    }
 
-        let res = run_command(&cmd, Some(env));
+    /// Prefix all output of restic with `prefix`
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
 
-        if res.2 == 0 {
-            targets_results.insert(repo.repo.clone(), Ok(()));
-        } else {
-            let err = ResticError::from_code(res.2).unwrap();
-            targets_results.insert(repo.repo.clone(), Err(err));
+    /// Start a new restic invocation for `cmd` on this repository.
+    pub fn invocation(&self, cmd: ResticCommand) -> ResticInvocation {
+        let mut args = vec![
+            "restic".to_string(),
+            cmd.as_str().to_string(),
+            "-r".to_string(),
+            self.repo.clone(),
+        ];
+
+        if let Some(ssh_opt) = &self.ssh_opt {
+            args.push("-o".to_string());
+            args.push(ssh_opt.clone());
+        }
+
+        ResticInvocation {
+            args,
+            env: self.env.clone(),
+            prefix: self.prefix.clone(),
         }
     }
+}
 
-    targets_results
+/// A single restic command line including its environment.
+#[derive(Debug, Clone)]
+pub struct ResticInvocation {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    prefix: Option<String>,
 }
 
-pub fn find_password(password: &Option<String>, pass_file: &Option<String>) -> Option<String> {
-    match password {
-        Some(_) => {
-            return password.clone();
+impl ResticInvocation {
+    pub fn arg(&mut self, arg: impl Into<String>) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<S: AsRef<str>>(&mut self, args: &[S]) -> &mut Self {
+        self.args
+            .extend(args.iter().map(|x| x.as_ref().to_string()));
+        self
+    }
+
+    /// Add `flag` if `enabled`
+    pub fn flag(&mut self, flag: &str, enabled: bool) -> &mut Self {
+        if enabled {
+            self.args.push(flag.to_string());
         }
-        None => {
-            if let Some(pass_file) = pass_file {
-                let passphrase =
-                    std::fs::read_to_string(pass_file).expect("Could not read passphrase file");
-                return Some(passphrase);
-            }
+        self
+    }
+
+    /// Add `flag <val>` if a value is set
+    pub fn option<T: ToString>(&mut self, flag: &str, val: Option<T>) -> &mut Self {
+        if let Some(val) = val {
+            self.args.push(flag.to_string());
+            self.args.push(val.to_string());
         }
+        self
     }
 
-    None
-}
+    /// Additional environment for restic and the commands it runs
+    pub fn envs(&mut self, env: Vec<(String, String)>) -> &mut Self {
+        self.env.extend(env);
+        self
+    }
 
-pub struct IntStrings {
-    // Keep the Box<str> so we can drop them safely
-    bufs: RefCell<Vec<Box<str>>>,
-}
+    /// Full command line starting with `restic`
+    pub fn get_args(&self) -> &[String] {
+        &self.args
+    }
 
-impl IntStrings {
-    pub fn new() -> Self {
-        Self {
-            bufs: RefCell::new(vec![]),
-        }
+    /// Environment variables passed to restic
+    pub fn get_envs(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    /// Ready to spawn process
+    pub fn command(&self) -> std::process::Command {
+        let mut cmd = std::process::Command::new(&self.args[0]);
+        cmd.args(&self.args[1..]);
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+        cmd
+    }
+
+    /// Run restic with output passed through
+    pub fn run(&self) -> Result<(), ResticError> {
+        let res = match &self.prefix {
+            Some(prefix) => {
+                run_command_prefixed(&self.str_args(), Some(self.env.clone()), prefix, false)
+            }
+            None => run_command(&self.str_args(), Some(self.env.clone())),
+        };
+        ResticError::check(res.2)
+    }
+
+    /// Run restic and capture its stdout
+    pub fn output(&self) -> Result<String, ResticError> {
+        let (res, output) = self.capture();
+        res.map(|_| output)
+    }
+
+    /// Run restic and capture its stdout regardless of the exit code
+    pub fn capture(&self) -> (Result<(), ResticError>, String) {
+        let res = match &self.prefix {
+            Some(prefix) => {
+                run_command_prefixed(&self.str_args(), Some(self.env.clone()), prefix, true)
+            }
+            None => run_command_output(&self.str_args(), Some(self.env.clone())),
+        };
+        (ResticError::check(res.2), res.0)
+    }
+
+    fn str_args(&self) -> Vec<&str> {
+        self.args.iter().map(|x| x.as_str()).collect()
     }
+}
 
-    /// Returns a `&str` pointing into owned storage
-    pub fn format(&self, num: u64) -> &str {
-        let s: Box<str> = num.to_string().into_boxed_str();
+/// `sftp.command` option for repositories with custom SSH options.
+pub fn ssh_option(repo: &ResticTarget) -> Option<String> {
+    let ssh = repo.ssh.as_ref()?;
+
+    let remote = repo.repo.trim_start_matches("sftp:");
+    let hostpart = remote.split(':').collect::<Vec<_>>();
+    let hostpart = hostpart.first().unwrap();
+    let (user, host) = hostpart.split_once('@').unwrap();
+    let ssh_cmd = format!(
+        "ssh -i {} {} -o StrictHostKeyChecking=no {user}@{host} -s sftp",
+        ssh.identity,
+        if let Some(p) = ssh.port {
+            format!("-p {p}")
+        } else {
+            String::new()
+        }
+    );
 
-        // Leak the string to get a reference that lives "forever"
-        let s_ref: &str = Box::leak(s);
+    Some(format!("sftp.command={ssh_cmd}"))
+}
 
-        // Store the box in the Vec so we can drop later
-        self.bufs
-            .borrow_mut()
-            .push(unsafe { Box::from_raw(s_ref as *const str as *mut str) });
+/// Resolve a secret given inline, as file, as output of a shell command or as systemd credential
+/// (in this order). Trailing newlines of files and command output are removed.
+///
+/// Returns `None` if no source is set or it could not be read.
+pub fn find_password(
+    password: &Option<String>,
+    pass_file: &Option<String>,
+    pass_command: &Option<String>,
+    pass_credential: &Option<String>,
+) -> Option<String> {
+    if password.is_some() {
+        return password.clone();
+    }
 
-        s_ref
+    let secret = if let Some(pass_file) = pass_file {
+        std::fs::read_to_string(pass_file)
+            .map_err(|e| format!("Could not read secret file {pass_file}: {e}"))
+    } else if let Some(pass_command) = pass_command {
+        let (output, _, code) = run_command_output(&["sh", "-c", pass_command], None);
+        if code == 0 {
+            Ok(output)
+        } else {
+            Err(format!(
+                "Secret command {pass_command} failed with exit code {code}"
+            ))
+        }
+    } else if let Some(credential) = pass_credential {
+        // systemd `LoadCredential=` places credentials in this directory
+        match std::env::var("CREDENTIALS_DIRECTORY") {
+            Ok(dir) => std::fs::read_to_string(std::path::Path::new(&dir).join(credential))
+                .map_err(|e| format!("Could not read credential {credential}: {e}")),
+            Err(_) => Err(format!(
+                "Could not read credential {credential}: CREDENTIALS_DIRECTORY is not set"
+            )),
+        }
+    } else {
+        return None;
+    };
+
+    match secret {
+        Ok(secret) => Some(secret.trim_end_matches(['\n', '\r']).to_string()),
+        Err(e) => {
+            log::error!("{e}");
+            None
+        }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum ResticError {
     /// Return Code 1 - fatal error (no snapshot created)
     Fatal,
@@ -503,6 +1005,15 @@ impl std::fmt::Display for ResticError {
 }
 
 impl ResticError {
+    /// Map a restic exit code to a result
+    pub fn check(code: i32) -> Result<(), Self> {
+        if code == 0 {
+            Ok(())
+        } else {
+            Err(Self::from_code(code).unwrap_or(Self::Fatal))
+        }
+    }
+
     pub fn from_code(code: i32) -> Option<Self> {
         match code {
             1 => Some(Self::Fatal),
@@ -514,3 +1025,305 @@ impl ResticError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(toml: &str) -> ResticTarget {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn passphrase_env() {
+        let repo = ResticRepo::new(&target(
+            r#"
+            repo = "/srv/restic"
+            passphrase = "secret"
+            "#,
+        ))
+        .unwrap();
+
+        let cmd = repo.invocation(ResticCommand::Snapshots);
+        assert_eq!(
+            cmd.get_envs(),
+            [("RESTIC_PASSWORD".to_string(), "secret".to_string())]
+        );
+    }
+
+    #[test]
+    fn passphrase_file_is_trimmed() {
+        let file = std::env::temp_dir().join(format!("bk-test-pass-{}", std::process::id()));
+        std::fs::write(&file, "secret\n").unwrap();
+
+        let repo = ResticRepo::new(&target(&format!(
+            "repo = \"/srv/restic\"\npassphrase_file = \"{}\"",
+            file.display()
+        )));
+        std::fs::remove_file(&file).unwrap();
+
+        let cmd = repo.unwrap().invocation(ResticCommand::Snapshots);
+        assert_eq!(cmd.get_envs()[0].1, "secret");
+    }
+
+    #[test]
+    fn passphrase_command() {
+        let repo = ResticRepo::new(&target(
+            "repo = \"/srv/restic\"\npassphrase_command = \"echo secret\"",
+        ));
+
+        let cmd = repo.unwrap().invocation(ResticCommand::Snapshots);
+        assert_eq!(cmd.get_envs()[0].1, "secret");
+    }
+
+    #[test]
+    fn passphrase_credential() {
+        let dir = std::env::temp_dir().join(format!("bk-test-creds-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("restic"), "secret\n").unwrap();
+
+        // SAFETY: no other test reads or writes CREDENTIALS_DIRECTORY
+        unsafe { std::env::set_var("CREDENTIALS_DIRECTORY", &dir) };
+        let repo = ResticRepo::new(&target(
+            "repo = \"/srv/restic\"\npassphrase_credential = \"restic\"",
+        ));
+        unsafe { std::env::remove_var("CREDENTIALS_DIRECTORY") };
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let cmd = repo.unwrap().invocation(ResticCommand::Snapshots);
+        assert_eq!(cmd.get_envs()[0].1, "secret");
+    }
+
+    #[test]
+    fn missing_passphrase() {
+        let res = ResticRepo::new(&target(r#"repo = "/srv/restic""#));
+        assert_eq!(res.unwrap_err(), ResticError::Fatal);
+    }
+
+    #[test]
+    fn s3_env() {
+        let repo = ResticRepo::new(&target(
+            r#"
+            repo = "s3:https://s3.example.com/bucket"
+            passphrase = "secret"
+            s3.access_key = "access"
+            s3.secret_key = "key"
+            "#,
+        ))
+        .unwrap();
+
+        let cmd = repo.invocation(ResticCommand::Backup);
+        assert_eq!(
+            cmd.get_envs(),
+            [
+                ("RESTIC_PASSWORD".to_string(), "secret".to_string()),
+                ("AWS_ACCESS_KEY_ID".to_string(), "access".to_string()),
+                ("AWS_SECRET_ACCESS_KEY".to_string(), "key".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn incomplete_s3_credentials() {
+        let res = ResticRepo::new(&target(
+            r#"
+            repo = "s3:https://s3.example.com/bucket"
+            passphrase = "secret"
+            s3.access_key = "access"
+            "#,
+        ));
+        assert_eq!(res.unwrap_err(), ResticError::Fatal);
+    }
+
+    #[test]
+    fn sftp_command() {
+        let repo = ResticRepo::new(&target(
+            r#"
+            repo = "sftp:backup@host.example.com:/srv/restic"
+            passphrase = "secret"
+            ssh.identity = "/root/.ssh/backup"
+            ssh.port = 2222
+            "#,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            repo.invocation(ResticCommand::Snapshots).get_args(),
+            [
+                "restic",
+                "snapshots",
+                "-r",
+                "sftp:backup@host.example.com:/srv/restic",
+                "-o",
+                "sftp.command=ssh -i /root/.ssh/backup -p 2222 -o StrictHostKeyChecking=no backup@host.example.com -s sftp",
+            ]
+        );
+    }
+
+    #[test]
+    fn no_sftp_command_without_ssh() {
+        let repo = ResticRepo::new(&target(
+            r#"
+            repo = "sftp:backup@host.example.com:/srv/restic"
+            passphrase = "secret"
+            "#,
+        ))
+        .unwrap();
+
+        assert!(
+            !repo
+                .invocation(ResticCommand::Snapshots)
+                .get_args()
+                .contains(&"-o".to_string())
+        );
+    }
+
+    #[test]
+    fn argument_order() {
+        let repo = ResticRepo::new(&target(
+            r#"
+            repo = "/srv/restic"
+            passphrase = "secret"
+            "#,
+        ))
+        .unwrap();
+
+        let mut cmd = repo.invocation(ResticCommand::Backup);
+        cmd.flag("--dry-run", true)
+            .flag("--force", false)
+            .option("--tag", Some("daily"))
+            .option("--host", None::<String>)
+            .option("--read-concurrency", Some(2))
+            .arg("--")
+            .args(&["/a", "/b"]);
+
+        assert_eq!(
+            cmd.get_args(),
+            [
+                "restic",
+                "backup",
+                "-r",
+                "/srv/restic",
+                "--dry-run",
+                "--tag",
+                "daily",
+                "--read-concurrency",
+                "2",
+                "--",
+                "/a",
+                "/b",
+            ]
+        );
+    }
+
+    /// Output of `restic backup --json` (restic 0.17) with a non JSON line and an unknown message
+    const BACKUP_OUTPUT: &str = r#"repository 3f1a2b3c opened (version 2, compression level auto)
+{"message_type":"status","percent_done":0,"total_files":1,"total_bytes":38}
+{"message_type":"status","seconds_elapsed":1,"percent_done":0.5,"total_files":10,"files_done":5,"total_bytes":2048,"bytes_done":1024,"current_files":["/home/user/a.txt","/home/user/b.txt"]}
+{"message_type":"verbose_status","action":"new","item":"/home/user/a.txt","duration":0.01,"data_size":38,"metadata_size":0,"total_files":1}
+{"message_type":"error","error":{"message":"open /home/user/secret: permission denied"},"during":"archival","item":"/home/user/secret"}
+{"message_type":"summary","files_new":2,"files_changed":1,"files_unmodified":7,"dirs_new":0,"dirs_changed":1,"dirs_unmodified":3,"data_blobs":3,"tree_blobs":2,"data_added":1288490188,"data_added_packed":900000,"total_files_processed":10,"total_bytes_processed":2048,"total_duration":1.25,"backup_start":"2026-10-17T10:00:00.1+02:00","backup_end":"2026-10-17T10:00:01.35+02:00","snapshot_id":"4f2a1b3c9d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a"}
+"#;
+
+    #[test]
+    fn parse_backup_messages() {
+        let messages = parse_backup_output(BACKUP_OUTPUT);
+
+        assert_eq!(messages.len(), 5);
+        assert!(matches!(messages[0], BackupMessage::Status(_)));
+        assert!(matches!(messages[2], BackupMessage::Unknown));
+
+        let BackupMessage::Status(status) = &messages[1] else {
+            panic!("expected status, got {:?}", messages[1]);
+        };
+        assert_eq!(status.files_done, 5);
+        assert_eq!(status.current_files.len(), 2);
+
+        let BackupMessage::Error(error) = &messages[3] else {
+            panic!("expected error, got {:?}", messages[3]);
+        };
+        assert_eq!(error.during, "archival");
+        assert_eq!(error.item, "/home/user/secret");
+        assert_eq!(error.message(), "open /home/user/secret: permission denied");
+    }
+
+    #[test]
+    fn backup_summary() {
+        let summary = handle_backup_output(BACKUP_OUTPUT).unwrap();
+
+        assert_eq!(summary.files_new, 2);
+        assert_eq!(summary.files_changed, 1);
+        assert_eq!(summary.data_added, 1288490188);
+        assert_eq!(
+            summary.to_string(),
+            "1.2 GiB added, 2 new and 1 changed files, snapshot 4f2a1b3c"
+        );
+    }
+
+    #[test]
+    fn dry_run_summary_without_snapshot() {
+        let summary = handle_backup_output(
+            r#"{"message_type":"summary","files_new":1,"files_changed":0,"data_added":512,"dry_run":true}"#,
+        )
+        .unwrap();
+
+        assert_eq!(summary.snapshot_id, None);
+        assert_eq!(
+            summary.to_string(),
+            "512 B added, 1 new and 0 changed files"
+        );
+    }
+
+    #[test]
+    fn exit_error() {
+        let messages = parse_backup_output(
+            r#"{"message_type":"exit_error","code":1,"message":"Fatal: unable to open config file: stat /srv/restic/config: no such file or directory\nIs there a repository at the following location?\n/srv/restic"}"#,
+        );
+
+        let [BackupMessage::ExitError(error)] = messages.as_slice() else {
+            panic!("expected exit error, got {messages:?}");
+        };
+        assert_eq!(error.code, 1);
+        assert!(
+            error
+                .message
+                .starts_with("Fatal: unable to open config file")
+        );
+        assert!(handle_backup_output("").is_none());
+    }
+
+    #[test]
+    fn error_formats() {
+        // Older restic versions serialize the Go error struct or a plain string
+        let messages = parse_backup_output(
+            r#"{"message_type":"error","error":{"Op":"lstat","Path":"/x","Err":2},"during":"scan","item":"/x"}
+{"message_type":"error","error":"read /y: input/output error","during":"archival","item":"/y"}"#,
+        );
+
+        let messages: Vec<_> = messages
+            .iter()
+            .map(|x| match x {
+                BackupMessage::Error(e) => e.message(),
+                other => panic!("expected error, got {other:?}"),
+            })
+            .collect();
+
+        assert_eq!(
+            messages,
+            [
+                r#"{"Err":2,"Op":"lstat","Path":"/x"}"#,
+                "read /y: input/output error"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_lines_are_skipped() {
+        let messages = parse_backup_output(
+            "\n{not json\n  {\"message_type\":\"status\",\"percent_done\":1}  \nFatal: wrong password\n",
+        );
+
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0], BackupMessage::Status(_)));
+    }
+}
@@ -0,0 +1,76 @@
+use yansi::{Color, Paint};
+
+use crate::{args::RestoreCommand, config::Config, restic};
+
+pub fn run_restore(args: RestoreCommand) -> i32 {
+    let conf = match Config::from_path(&args.config) {
+        Ok(conf) => conf,
+        Err(e) => {
+            log::error!("{e}");
+            return 1;
+        }
+    };
+
+    let targets = conf.restic_target.unwrap_or_default();
+    let Some(target) = targets.get(&args.target) else {
+        log::error!("Unknown restic provider {}", args.target);
+        return 1;
+    };
+
+    // Restoring a path input puts it back to its original location
+    let mut archived_path = None;
+    let mut dest = args.dest.clone();
+
+    if let Some(path_name) = &args.path {
+        let paths = conf.path.unwrap_or_default();
+        let Some(path) = paths.get(path_name) else {
+            log::error!("Unknown path provider {path_name}");
+            return 1;
+        };
+
+        let Some(archived) = path.archived_path() else {
+            log::error!(
+                "Path {path_name} is stored under a changing snapshot directory. Enable same_path or restore manually."
+            );
+            return 1;
+        };
+
+        archived_path = Some(archived);
+
+        if dest.is_none() {
+            dest = Some(path.path.clone());
+        }
+    }
+
+    let Some(dest) = dest else {
+        log::error!("No restore destination provided. Use --dest or --path.");
+        return 1;
+    };
+
+    if args.dry_run {
+        log::warn!("Running in dry run mode. No files will be restored.");
+    }
+
+    match restic::restore_archive(
+        target,
+        &args.snapshot,
+        &dest,
+        &args.include,
+        &args.exclude,
+        archived_path.as_deref(),
+        args.dry_run,
+    ) {
+        Ok(_) => {
+            log::info!("Restore successfull to {dest}");
+            0
+        }
+        Err(e) => {
+            println!(
+                "{} Restore from {} failed: {e}",
+                "Error:".paint(Color::Red),
+                args.target
+            );
+            1
+        }
+    }
+}
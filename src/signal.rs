@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Last received termination signal (0 if none)
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+const MAX_CHILDREN: usize = 32;
+
+/// PIDs of currently running child processes the signal gets forwarded to
+static CHILDREN: [AtomicI32; MAX_CHILDREN] = [const { AtomicI32::new(0) }; MAX_CHILDREN];
+
+extern "C" fn handle_signal(sig: libc::c_int) {
+    SIGNAL.store(sig, Ordering::SeqCst);
+
+    // Only async-signal-safe operations in here
+    for child in &CHILDREN {
+        let pid = child.load(Ordering::SeqCst);
+        if pid > 0 {
+            unsafe {
+                libc::kill(pid, sig);
+            }
+        }
+    }
+}
+
+/// Install handlers for SIGINT and SIGTERM.
+///
+/// Instead of terminating immediately, the signal is forwarded to running child processes and
+/// recorded so the current operation can wind down and clean up.
+pub fn install() {
+    for sig in [libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(sig, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// The termination signal received, if any
+pub fn received() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        sig => Some(sig),
+    }
+}
+
+/// Whether bk should stop starting new work
+pub fn interrupted() -> bool {
+    received().is_some()
+}
+
+/// Exit code for a run interrupted by a signal (128 + signal number)
+pub fn exit_code() -> Option<i32> {
+    received().map(|sig| 128 + sig)
+}
+
+/// Forward signals to the child `pid` while it runs
+pub fn register_child(pid: u32) {
+    for child in &CHILDREN {
+        if child
+            .compare_exchange(0, pid as i32, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return;
+        }
+    }
+
+    log::warn!("Too many child processes. Signals will not be forwarded to {pid}");
+}
+
+pub fn unregister_child(pid: u32) {
+    for child in &CHILDREN {
+        if child
+            .compare_exchange(pid as i32, 0, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
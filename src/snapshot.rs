@@ -0,0 +1,514 @@
+use std::{os::unix::fs::MetadataExt, path::Path};
+
+use chrono::NaiveDateTime;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{backup::nowtime, cleanup::MOUNT_DIR, restic::umount, run_command, run_command_output};
+
+/// Snapshot taken of an input before the backup
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SnapshotConfig {
+    /// CephFS snapshot in `<dir>/.snap`
+    Cephfs,
+
+    /// Read-only btrfs subvolume snapshot in `<dir>/.bk_snap`
+    Btrfs,
+
+    /// ZFS snapshot of the dataset containing the directory
+    Zfs,
+
+    /// LVM snapshot mounted read-only below `/bk`
+    Lvm {
+        /// The volume to snapshot as `vg/lv`
+        volume: String,
+
+        /// Size of the snapshot. Only needed for non thin volumes.
+        size: Option<String>,
+
+        /// Additional mount options (e.g. `nouuid` for XFS)
+        mount_options: Option<String>,
+    },
+}
+
+impl std::fmt::Display for SnapshotConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotConfig::Cephfs => write!(f, "CephFS"),
+            SnapshotConfig::Btrfs => write!(f, "btrfs"),
+            SnapshotConfig::Zfs => write!(f, "ZFS"),
+            SnapshotConfig::Lvm { .. } => write!(f, "LVM"),
+        }
+    }
+}
+
+impl SnapshotConfig {
+    /// Provider taking snapshots of `dir`
+    pub fn provider(&self, dir: &str) -> Box<dyn SnapshotProvider> {
+        let dir = dir.to_string();
+        match self {
+            SnapshotConfig::Cephfs => Box::new(CephfsSnapshot { dir, snap: None }),
+            SnapshotConfig::Btrfs => Box::new(BtrfsSnapshot { dir, snap: None }),
+            SnapshotConfig::Zfs => Box::new(ZfsSnapshot {
+                dir,
+                snapshot: None,
+            }),
+            SnapshotConfig::Lvm {
+                volume,
+                size,
+                mount_options,
+            } => Box::new(LvmSnapshot {
+                dir,
+                volume: volume.clone(),
+                size: size.clone(),
+                mount_options: mount_options.clone(),
+                snapshot: None,
+            }),
+        }
+    }
+}
+
+/// A snapshot backend
+pub trait SnapshotProvider {
+    /// Take a snapshot and return the directory to back up instead of the original one.
+    ///
+    /// Returns why the snapshot could not be taken otherwise. Nothing is left behind in that case.
+    fn create(&mut self) -> Result<String, String>;
+
+    /// Directory of the current snapshot if one was taken
+    fn path(&self) -> Option<String>;
+
+    /// Remove the current snapshot
+    fn remove(&mut self);
+
+    /// Snapshots left behind by runs started before `before`
+    fn stale(&self, before: NaiveDateTime) -> Vec<String>;
+
+    /// Remove a snapshot returned by `stale`. Returns whether it was removed.
+    fn remove_stale(&self, snapshot: &str) -> bool;
+}
+
+fn dir_path(dir: &Path) -> String {
+    format!("{}/", dir.to_str().unwrap().trim_end_matches('/'))
+}
+
+/// Path of `dir` inside the filesystem mounted at `root`
+fn relative_to<'a>(dir: &'a str, root: &str) -> Result<&'a Path, String> {
+    match Path::new(dir).strip_prefix(root) {
+        Ok(relative) if !relative.is_absolute() => Ok(relative),
+        _ => Err(format!("{dir} is not below {root}")),
+    }
+}
+
+/// Parse a timestamp as created by [`nowtime`]. Older versions only used the date for some snapshots.
+pub fn parse_time(time: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(time, "%Y_%m_%d-%H_%M")
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(time, "%Y_%m_%d")
+                .map(|x| x.and_time(chrono::NaiveTime::MIN))
+        })
+        .ok()
+}
+
+/// Snapshots created by bk (`SNAP_<time>`) before `before` in `snap_dir`
+fn dated_snapshots(snap_dir: &Path, before: NaiveDateTime) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(snap_dir) else {
+        return Vec::new();
+    };
+
+    let mut snaps: Vec<_> = entries
+        .flatten()
+        .filter_map(|x| x.file_name().into_string().ok())
+        .filter(|x| {
+            x.strip_prefix("SNAP_")
+                .and_then(parse_time)
+                .is_some_and(|time| time < before)
+        })
+        .map(|x| snap_dir.join(x).to_str().unwrap().to_string())
+        .collect();
+
+    snaps.sort();
+    snaps
+}
+
+// CephFS
+
+pub struct CephfsSnapshot {
+    dir: String,
+    snap: Option<String>,
+}
+
+impl CephfsSnapshot {
+    fn snap_dir(&self) -> std::path::PathBuf {
+        Path::new(&self.dir).join(".snap")
+    }
+}
+
+impl SnapshotProvider for CephfsSnapshot {
+    fn create(&mut self) -> Result<String, String> {
+        let snap_name = format!("SNAP_{}", nowtime());
+        let snap_dir = self.snap_dir().join(&snap_name);
+
+        log::info!("Creating snapshot {} on {}", snap_name, self.dir);
+        if let Err(e) = std::fs::create_dir(&snap_dir)
+            && !std::fs::exists(&snap_dir).unwrap_or_default()
+        {
+            return Err(format!("could not create {}: {e}", snap_dir.display()));
+        }
+
+        self.snap = Some(snap_name);
+        Ok(dir_path(&snap_dir))
+    }
+
+    fn path(&self) -> Option<String> {
+        self.snap
+            .as_ref()
+            .map(|x| dir_path(&self.snap_dir().join(x)))
+    }
+
+    fn remove(&mut self) {
+        if let Some(snap) = self.snap.take() {
+            log::info!("Removing snapshot {} on {}", snap, self.dir);
+            if let Err(e) = std::fs::remove_dir(self.snap_dir().join(&snap)) {
+                log::error!("Could not remove snapshot {}@{snap}: {e}", self.dir);
+            }
+        }
+    }
+
+    fn stale(&self, before: NaiveDateTime) -> Vec<String> {
+        dated_snapshots(&self.snap_dir(), before)
+    }
+
+    fn remove_stale(&self, snapshot: &str) -> bool {
+        match std::fs::remove_dir(snapshot) {
+            Ok(_) => true,
+            Err(e) => {
+                log::error!("Could not remove snapshot {snapshot}: {e}");
+                false
+            }
+        }
+    }
+}
+
+// btrfs
+
+/// Directory btrfs snapshots are created in, relative to the snapshotted subvolume
+pub const BTRFS_SNAP_DIR: &str = ".bk_snap";
+
+pub struct BtrfsSnapshot {
+    dir: String,
+    snap: Option<String>,
+}
+
+impl BtrfsSnapshot {
+    fn snap_dir(&self) -> std::path::PathBuf {
+        Path::new(&self.dir).join(BTRFS_SNAP_DIR)
+    }
+}
+
+impl SnapshotProvider for BtrfsSnapshot {
+    fn create(&mut self) -> Result<String, String> {
+        let snap_name = format!("SNAP_{}", nowtime());
+        let snap_base = self.snap_dir();
+        let snap_dir = snap_base.join(&snap_name);
+
+        log::info!("Creating btrfs snapshot {} on {}", snap_name, self.dir);
+
+        // btrfs would create the snapshot inside an existing one
+        if std::fs::exists(&snap_dir).unwrap_or(true) {
+            return Err(format!("{} already exists", snap_dir.display()));
+        }
+
+        if let Err(e) = std::fs::create_dir_all(&snap_base) {
+            return Err(format!("could not create {}: {e}", snap_base.display()));
+        }
+
+        if run_command(
+            &[
+                "btrfs",
+                "subvolume",
+                "snapshot",
+                "-r",
+                &self.dir,
+                snap_dir.to_str().unwrap(),
+            ],
+            None,
+        )
+        .2 != 0
+        {
+            return Err("btrfs subvolume snapshot failed".to_string());
+        }
+
+        self.snap = Some(snap_name);
+        Ok(dir_path(&snap_dir))
+    }
+
+    fn path(&self) -> Option<String> {
+        self.snap
+            .as_ref()
+            .map(|x| dir_path(&self.snap_dir().join(x)))
+    }
+
+    fn remove(&mut self) {
+        if let Some(snap) = self.snap.take() {
+            log::info!("Removing btrfs snapshot {} on {}", snap, self.dir);
+            self.remove_stale(self.snap_dir().join(&snap).to_str().unwrap());
+        }
+    }
+
+    fn stale(&self, before: NaiveDateTime) -> Vec<String> {
+        dated_snapshots(&self.snap_dir(), before)
+    }
+
+    fn remove_stale(&self, snapshot: &str) -> bool {
+        run_command(&["btrfs", "subvolume", "delete", snapshot], None).2 == 0
+    }
+}
+
+// ZFS
+
+/// Dataset and its mountpoint containing `dir`
+fn zfs_dataset(dir: &str) -> Option<(String, String)> {
+    let res = run_command_output(&["zfs", "list", "-H", "-o", "name,mountpoint", dir], None);
+    if res.2 != 0 {
+        return None;
+    }
+
+    let (name, mountpoint) = res.0.lines().next()?.split_once('\t')?;
+    Some((name.to_string(), mountpoint.to_string()))
+}
+
+pub struct ZfsSnapshot {
+    dir: String,
+    /// Snapshot as `<dataset>@<name>` and the directory it is accessible at
+    snapshot: Option<(String, String)>,
+}
+
+impl SnapshotProvider for ZfsSnapshot {
+    fn create(&mut self) -> Result<String, String> {
+        let Some((dataset, mountpoint)) = zfs_dataset(&self.dir) else {
+            return Err(format!("could not find ZFS dataset for {}", self.dir));
+        };
+
+        // Datasets with a `legacy` or `none` mountpoint have no known snapshot directory
+        if !Path::new(&mountpoint).is_absolute() {
+            return Err(format!("ZFS dataset {dataset} has mountpoint {mountpoint}"));
+        }
+
+        // The path may be a subdirectory of the dataset
+        let relative = relative_to(&self.dir, &mountpoint)?;
+
+        let snap_name = format!("bk-{}", nowtime());
+        let snapshot = format!("{dataset}@{snap_name}");
+
+        log::info!("Creating ZFS snapshot {}", snapshot);
+        if run_command(&["zfs", "snapshot", &snapshot], None).2 != 0 {
+            return Err("zfs snapshot failed".to_string());
+        }
+
+        let snap_dir = dir_path(
+            &Path::new(&mountpoint)
+                .join(".zfs/snapshot")
+                .join(&snap_name)
+                .join(relative),
+        );
+
+        self.snapshot = Some((snapshot, snap_dir.clone()));
+        Ok(snap_dir)
+    }
+
+    fn path(&self) -> Option<String> {
+        self.snapshot.as_ref().map(|(_, dir)| dir.clone())
+    }
+
+    fn remove(&mut self) {
+        if let Some((snapshot, _)) = self.snapshot.take() {
+            log::info!("Removing ZFS snapshot {}", snapshot);
+            self.remove_stale(&snapshot);
+        }
+    }
+
+    fn stale(&self, before: NaiveDateTime) -> Vec<String> {
+        let Some((dataset, _)) = zfs_dataset(&self.dir) else {
+            return Vec::new();
+        };
+
+        let res = run_command_output(
+            &[
+                "zfs", "list", "-H", "-t", "snapshot", "-o", "name", &dataset,
+            ],
+            None,
+        );
+
+        res.0
+            .lines()
+            .filter(|x| {
+                x.split_once('@').is_some_and(|(ds, snap)| {
+                    ds == dataset
+                        && snap
+                            .strip_prefix("bk-")
+                            .and_then(parse_time)
+                            .is_some_and(|time| time < before)
+                })
+            })
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    fn remove_stale(&self, snapshot: &str) -> bool {
+        run_command(&["zfs", "destroy", snapshot], None).2 == 0
+    }
+}
+
+// LVM
+
+pub struct LvmSnapshot {
+    dir: String,
+    volume: String,
+    size: Option<String>,
+    mount_options: Option<String>,
+    snapshot: Option<LvmMount>,
+}
+
+/// An LVM snapshot mounted for the backup
+struct LvmMount {
+    /// Snapshot volume as `vg/lv`
+    volume: String,
+    /// Directory the snapshot is mounted to
+    mount_dir: String,
+    /// Path to back up inside the mounted snapshot
+    target_dir: String,
+}
+
+impl LvmSnapshot {
+    /// Name prefix of snapshot volumes created by bk for `lv`
+    fn prefix(lv: &str) -> String {
+        format!("{lv}_bk_")
+    }
+}
+
+impl SnapshotProvider for LvmSnapshot {
+    fn create(&mut self) -> Result<String, String> {
+        let Some((vg, lv)) = self.volume.split_once('/') else {
+            return Err(format!(
+                "invalid LVM volume {}. Expected vg/lv",
+                self.volume
+            ));
+        };
+
+        // The path may be a subdirectory of the volume
+        let (volume_root, _, code) = run_command_output(
+            &["findmnt", "-n", "-o", "TARGET", "--target", &self.dir],
+            None,
+        );
+        if code != 0 {
+            return Err(format!("could not find the mount of {}", self.dir));
+        }
+        let volume_root = volume_root.trim();
+
+        let origin = format!("/dev/{}", self.volume);
+        let on_volume = match (std::fs::metadata(volume_root), std::fs::metadata(&origin)) {
+            (Ok(root), Ok(device)) => root.dev() == device.rdev(),
+            _ => false,
+        };
+        if !on_volume {
+            return Err(format!("{volume_root} is not a mount of {origin}"));
+        }
+        let relative = relative_to(&self.dir, volume_root)?;
+
+        let snap_name = format!("{}{}", Self::prefix(lv), nowtime());
+        let snap_volume = format!("{vg}/{snap_name}");
+
+        log::info!("Creating LVM snapshot {} of {}", snap_volume, self.volume);
+
+        // Thin snapshots need no size but are skipped on activation by default
+        let mut cmd = vec!["lvcreate", "-s", "-kn", "-n", &snap_name];
+        if let Some(size) = &self.size {
+            cmd.extend(["-L", size]);
+        }
+        cmd.push(&self.volume);
+
+        if run_command(&cmd, None).2 != 0 {
+            return Err("lvcreate failed".to_string());
+        }
+
+        let mount_dir = format!("{MOUNT_DIR}lvm_{vg}_{lv}");
+        let options = match &self.mount_options {
+            Some(opts) => format!("ro,{opts}"),
+            None => "ro".to_string(),
+        };
+
+        let device = format!("/dev/{snap_volume}");
+        if std::fs::create_dir_all(&mount_dir).is_err()
+            || run_command(&["mount", "-o", &options, &device, &mount_dir], None).2 != 0
+        {
+            self.remove_stale(&snap_volume);
+            return Err(format!("could not mount {device} to {mount_dir}"));
+        }
+
+        let target_dir = dir_path(&Path::new(&mount_dir).join(relative));
+
+        self.snapshot = Some(LvmMount {
+            volume: snap_volume,
+            mount_dir,
+            target_dir: target_dir.clone(),
+        });
+        Ok(target_dir)
+    }
+
+    fn path(&self) -> Option<String> {
+        self.snapshot.as_ref().map(|x| x.target_dir.clone())
+    }
+
+    fn remove(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            log::info!("Removing LVM snapshot {}", snapshot.volume);
+            umount(&snapshot.mount_dir);
+            self.remove_stale(&snapshot.volume);
+        }
+    }
+
+    fn stale(&self, before: NaiveDateTime) -> Vec<String> {
+        let Some((vg, lv)) = self.volume.split_once('/') else {
+            return Vec::new();
+        };
+
+        let res = run_command_output(&["lvs", "--noheadings", "-o", "lv_name", vg], None);
+        let prefix = Self::prefix(lv);
+
+        res.0
+            .lines()
+            .map(|x| x.trim())
+            .filter(|x| {
+                x.strip_prefix(&prefix)
+                    .and_then(parse_time)
+                    .is_some_and(|time| time < before)
+            })
+            .map(|x| format!("{vg}/{x}"))
+            .collect()
+    }
+
+    fn remove_stale(&self, snapshot: &str) -> bool {
+        run_command(&["lvremove", "-y", snapshot], None).2 == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_to_mount() {
+        assert_eq!(relative_to("/srv/data/a", "/srv/data"), Ok(Path::new("a")));
+        assert_eq!(relative_to("/srv/data", "/srv/data"), Ok(Path::new("")));
+        assert_eq!(relative_to("/srv/data/", "/"), Ok(Path::new("srv/data")));
+    }
+
+    #[test]
+    fn relative_to_other_mount() {
+        assert!(relative_to("/srv/data", "/srv/database").is_err());
+        assert!(relative_to("/srv/data", "").is_err());
+        assert!(relative_to("/srv/data", "legacy").is_err());
+    }
+}
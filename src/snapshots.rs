@@ -0,0 +1,125 @@
+use yansi::{Color, Paint};
+
+use crate::{args::SnapshotsCommand, config::Config, restic, signal};
+
+pub fn list_snapshots(args: SnapshotsCommand) -> i32 {
+    let conf = match Config::from_path(&args.config) {
+        Ok(conf) => conf,
+        Err(e) => {
+            log::error!("{e}");
+            return 1;
+        }
+    };
+    let mut state = 0;
+
+    let mut paths = Vec::new();
+    let path_provider = conf.path.unwrap_or_default();
+
+    for path_name in &args.path {
+        let Some(path) = path_provider.get(path_name) else {
+            log::error!("Unknown path provider {path_name}");
+            return 1;
+        };
+
+        let Some(archived) = path.archived_path() else {
+            log::error!("Path {path_name} is stored under a changing snapshot directory");
+            return 1;
+        };
+
+        paths.push(archived);
+    }
+
+    let target_provider = conf.restic_target.unwrap_or_default();
+    let mut targets: Vec<_> = target_provider
+        .iter()
+        .filter(|(name, _)| args.target.is_empty() || args.target.contains(name))
+        .collect();
+    targets.sort_by_key(|(name, _)| name.as_str());
+
+    for name in &args.target {
+        if !target_provider.contains_key(name) {
+            log::error!("Unknown restic provider {name}");
+            return 1;
+        }
+    }
+
+    let mut rows = Vec::new();
+
+    for (name, repo) in targets {
+        if let Some(code) = signal::exit_code() {
+            log::warn!("Interrupted. Remaining targets were skipped.");
+            return code;
+        }
+
+        match restic::list_snapshots(repo, &args.host, &args.tag, &paths) {
+            Ok(snapshots) => {
+                for snap in snapshots {
+                    let time = chrono::DateTime::parse_from_rfc3339(&snap.time).ok();
+                    rows.push((name.clone(), snap, time));
+                }
+            }
+            Err(e) => {
+                log::error!("Listing snapshots of {name} failed: {e}");
+                state = 1;
+            }
+        }
+    }
+
+    // The last target may have been interrupted
+    if let Some(code) = signal::exit_code() {
+        return code;
+    }
+
+    // Times carry the offset of each host, so only the parsed values compare correctly
+    rows.sort_by_key(|(_, _, time)| *time);
+
+    let header = ["Target", "ID", "Time", "Host", "Paths", "Tags"];
+    let table: Vec<[String; 6]> = rows
+        .into_iter()
+        .map(|(target, snap, time)| {
+            let time = time
+                .map(|x| {
+                    x.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string()
+                })
+                .unwrap_or(snap.time);
+
+            [
+                target,
+                snap.short_id
+                    .unwrap_or_else(|| snap.id.chars().take(8).collect()),
+                time,
+                snap.hostname,
+                snap.paths.join(", "),
+                snap.tags.unwrap_or_default().join(", "),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(|x| x.len());
+    for row in &table {
+        for (i, col) in row.iter().enumerate() {
+            widths[i] = widths[i].max(col.chars().count());
+        }
+    }
+
+    let format_row = |row: &[&str]| {
+        row.iter()
+            .enumerate()
+            .map(|(i, col)| format!("{col:<width$}", width = widths[i]))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(&header).paint(Color::Yellow));
+    for row in &table {
+        println!("{}", format_row(&row.each_ref().map(|x| x.as_str())));
+    }
+
+    println!("{} snapshots", table.len());
+
+    state
+}